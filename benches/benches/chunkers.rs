@@ -0,0 +1,108 @@
+//! Measures the speed of each `ChunkerImpl`, through each way of getting
+//! chunks, on random and highly redundant data.
+//!
+//! Statistics about the chunks found, and how many survive random edits, are
+//! printed before each group, to help picking parameters.
+
+use cdchunking::{ChunkInput, ChunkStats, Chunker, ChunkerImpl, Gear, ZPAQ};
+use criterion::{
+    black_box, criterion_group, criterion_main, Criterion, Throughput,
+};
+
+mod common;
+
+use common::random_data;
+
+const SIZE: usize = 4 << 20;
+
+/// The same block repeated over and over, with a few bytes changed in each
+/// copy.
+fn redundant_data() -> Vec<u8> {
+    let block = random_data(256 << 10, 1);
+    let mut data = Vec::with_capacity(SIZE);
+    let mut copy = 0;
+    while data.len() < SIZE {
+        let start = data.len();
+        data.extend_from_slice(&block);
+        data[start + (copy * 7919) % block.len()] ^= 0xff;
+        copy += 1;
+    }
+    data.truncate(SIZE);
+    data
+}
+
+fn print_stats<I, F>(name: &str, chunker: &F, data: &[u8])
+where
+    I: ChunkerImpl + Clone,
+    F: Fn() -> Chunker<I>,
+{
+    let mut stats = ChunkStats::new();
+    stats.extend(chunker().chunks(data).map(|c| c.unwrap()));
+    println!("{}: {}", name, stats);
+    println!("after 16 edits: {}\n", chunker().resilience(&[data], 16, 0));
+}
+
+fn bench_chunker<I, F>(c: &mut Criterion, name: &str, chunker: F)
+where
+    I: ChunkerImpl + Clone,
+    F: Fn() -> Chunker<I>,
+{
+    let datasets = [
+        ("random", random_data(SIZE, 0)),
+        ("redundant", redundant_data()),
+    ];
+    for &(kind, ref data) in datasets.iter() {
+        let name = format!("{}/{}", name, kind);
+        print_stats(&name, &chunker, data);
+
+        let mut group = c.benchmark_group(&name);
+        group.throughput(Throughput::Bytes(data.len() as u64));
+        group.sample_size(10);
+        group.bench_function("slices", |b| {
+            b.iter(|| chunker().slices(black_box(data)).count())
+        });
+        group.bench_function("stream", |b| {
+            b.iter(|| {
+                let mut count = 0;
+                let mut stream = chunker().stream(black_box(&data[..]));
+                while let Some(chunk) = stream.read() {
+                    if let ChunkInput::End = chunk.unwrap() {
+                        count += 1;
+                    }
+                }
+                count
+            })
+        });
+        group.bench_function("whole_chunks", |b| {
+            b.iter(|| {
+                chunker()
+                    .whole_chunks(black_box(&data[..]))
+                    .map(|c| c.unwrap().len())
+                    .sum::<usize>()
+            })
+        });
+        group.bench_function("chunks", |b| {
+            b.iter(|| {
+                chunker()
+                    .chunks(black_box(&data[..]))
+                    .map(|c| c.unwrap().length())
+                    .sum::<u64>()
+            })
+        });
+        group.finish();
+    }
+}
+
+fn chunkers(c: &mut Criterion) {
+    bench_chunker(c, "zpaq-13", || Chunker::new(ZPAQ::new(13)));
+    bench_chunker(c, "zpaq-13-max-64k", || {
+        Chunker::new(ZPAQ::new(13)).max_size(64 << 10)
+    });
+    bench_chunker(c, "gear-13", || Chunker::new(Gear::new(13)));
+    bench_chunker(c, "gear-13-max-64k", || {
+        Chunker::new(Gear::new(13)).max_size(64 << 10)
+    });
+}
+
+criterion_group!(benches, chunkers);
+criterion_main!(benches);
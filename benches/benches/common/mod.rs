@@ -0,0 +1,11 @@
+//! Helpers shared by the benchmarks.
+
+// The library's generator, which it doesn't export
+#[allow(dead_code)]
+#[path = "../../../src/xorshift.rs"]
+mod xorshift;
+
+/// Pseudo-random data, the same as the library's tests use for a seed.
+pub fn random_data(size: usize, seed: u64) -> Vec<u8> {
+    xorshift::XorShift::new(seed).bytes(size)
+}
@@ -0,0 +1,44 @@
+//! Compares the speed of `find_boundary()` of the different algorithms.
+//!
+//! Run with `cargo bench`, or `cargo bench --no-default-features` to measure
+//! the portable implementation of `Gear` instead of the SIMD one.
+
+use cdchunking::{ChunkerImpl, Gear, ZPAQ};
+use criterion::{
+    black_box, criterion_group, criterion_main, Criterion, Throughput,
+};
+
+mod common;
+
+use common::random_data;
+
+const SIZE: usize = 16 << 20;
+
+/// Finds all the boundaries in the data, returning how many there are.
+fn count_boundaries<I: ChunkerImpl>(mut inner: I, data: &[u8]) -> usize {
+    let mut count = 0;
+    let mut pos = 0;
+    while let Some(split) = inner.find_boundary(&data[pos..]) {
+        pos += split + 1;
+        count += 1;
+        inner.reset();
+    }
+    count
+}
+
+fn find_boundary(c: &mut Criterion) {
+    let data = random_data(SIZE, 0);
+    let mut group = c.benchmark_group("find_boundary");
+    group.throughput(Throughput::Bytes(SIZE as u64));
+    group.sample_size(20);
+    group.bench_function("zpaq-13", |b| {
+        b.iter(|| count_boundaries(ZPAQ::new(13), black_box(&data)))
+    });
+    group.bench_function("gear-13", |b| {
+        b.iter(|| count_boundaries(Gear::new(13), black_box(&data)))
+    });
+    group.finish();
+}
+
+criterion_group!(benches, find_boundary);
+criterion_main!(benches);
@@ -0,0 +1,431 @@
+//! Asynchronous counterparts of the `Read`-based chunk iterators.
+//!
+//! These work on any `futures_io::AsyncRead`. Readers implementing tokio's
+//! `AsyncRead` can be used through the `TokioCompat` adapter.
+
+use futures_core::Stream;
+use futures_io::AsyncRead;
+use std::future::Future;
+use std::io;
+use std::mem::swap;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use super::{
+    BoundaryKind, ChunkError, ChunkInfo, ChunkInput, Chunker, ChunkerImpl,
+    Emitter, BUF_SIZE,
+};
+
+impl<I: ChunkerImpl> Chunker<I> {
+    /// Reads chunks with zero allocations from an asynchronous reader.
+    ///
+    /// This is the asynchronous version of `stream()`.
+    ///
+    /// Example:
+    ///
+    /// ```edition2018
+    /// # use cdchunking::{Chunker, ChunkInput, ZPAQ};
+    /// # async fn example() {
+    /// # let chunker = Chunker::new(ZPAQ::new(13));
+    /// # let reader: &[u8] = b"abcdefghijklmnopqrstuvwxyz1234567890";
+    /// let mut chunk_iterator = chunker.async_stream(reader);
+    /// while let Some(chunk) = chunk_iterator.read().await {
+    ///     match chunk.unwrap() {
+    ///         ChunkInput::Data(d) => print!("{:?}, ", d),
+    ///         ChunkInput::End => println!(" end of chunk"),
+    ///     }
+    /// }
+    /// # }
+    /// ```
+    pub fn async_stream<R: AsyncRead + Unpin>(
+        self,
+        reader: R,
+    ) -> AsyncChunkStream<R, I> {
+        AsyncChunkStream {
+            reader,
+            emitter: Emitter::new(self.inner),
+            buffer: [0u8; BUF_SIZE],
+            pos: 0,
+            len: 0,
+        }
+    }
+
+    /// Iterates on whole chunks from an asynchronous reader.
+    ///
+    /// This is the asynchronous version of `whole_chunks()`, implementing
+    /// `Stream` instead of `Iterator`.
+    pub fn async_whole_chunks<R: AsyncRead + Unpin>(
+        self,
+        reader: R,
+    ) -> AsyncWholeChunks<R, I> {
+        AsyncWholeChunks {
+            stream: self.async_stream(reader),
+            buffer: Vec::new(),
+        }
+    }
+
+    /// Describes the chunks from an asynchronous reader.
+    ///
+    /// This is the asynchronous version of `chunks()`, implementing `Stream`
+    /// instead of `Iterator`.
+    pub fn async_chunks<R: AsyncRead + Unpin>(
+        self,
+        reader: R,
+    ) -> AsyncChunkInfoStream<R, I> {
+        AsyncChunkInfoStream {
+            stream: self.async_stream(reader),
+            last_chunk: 0,
+            pos: 0,
+        }
+    }
+}
+
+pub struct AsyncChunkStream<R: AsyncRead + Unpin, I: ChunkerImpl> {
+    reader: R,
+    emitter: Emitter<I>,
+    buffer: [u8; BUF_SIZE],
+    len: usize, // How much of the buffer has been read in from the reader
+    pos: usize, // Where are we in handling the buffer
+}
+
+// We never pin the inner chunker, so there is no reason to require it to be
+// Unpin
+impl<R: AsyncRead + Unpin, I: ChunkerImpl> Unpin for AsyncChunkStream<R, I> {}
+
+impl<R: AsyncRead + Unpin, I: ChunkerImpl> AsyncChunkStream<R, I> {
+    /// Iterate on the chunks, returning `ChunkInput` items.
+    ///
+    /// This works like `ChunkStream::read()`, returning a future. Errors are
+    /// handled the same way: interrupted reads are retried, and you can keep
+    /// reading after other errors.
+    pub fn read<'a>(&'a mut self) -> ReadChunk<'a, R, I> {
+        ReadChunk { stream: Some(self) }
+    }
+
+    /// Polling version of `read()`.
+    pub fn poll_read<'a>(
+        &'a mut self,
+        cx: &mut Context,
+    ) -> Poll<Option<Result<ChunkInput<'a>, ChunkError>>> {
+        if self.emitter.end_at_split() {
+            return Poll::Ready(Some(Ok(ChunkInput::End)));
+        }
+        match self.poll_fill(cx) {
+            Poll::Pending => Poll::Pending,
+            Poll::Ready(Err(e)) => {
+                Poll::Ready(Some(Err(self.emitter.error(e))))
+            }
+            Poll::Ready(Ok(())) => Poll::Ready(self.emit().map(Ok)),
+        }
+    }
+
+    /// Makes sure there is something to emit, reading from the reader if
+    /// necessary.
+    ///
+    /// If this returns with `pos == len`, we reached the end of the input.
+    fn poll_fill(&mut self, cx: &mut Context) -> Poll<io::Result<()>> {
+        if self.pos != self.len {
+            return Poll::Ready(Ok(()));
+        }
+        loop {
+            match Pin::new(&mut self.reader).poll_read(cx, &mut self.buffer) {
+                Poll::Pending => return Poll::Pending,
+                Poll::Ready(Err(ref e))
+                    if e.kind() == io::ErrorKind::Interrupted => {}
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                Poll::Ready(Ok(l)) => {
+                    self.pos = 0;
+                    self.len = l;
+                    return Poll::Ready(Ok(()));
+                }
+            }
+        }
+    }
+
+    /// Emits the next item, once `poll_fill()` is ready.
+    fn emit<'a>(&'a mut self) -> Option<ChunkInput<'a>> {
+        if self.pos == self.len {
+            if self.emitter.end_of_stream() {
+                return Some(ChunkInput::End);
+            }
+            return None;
+        }
+        let start = self.pos;
+        self.pos += self.emitter.split(&self.buffer[start..self.len]);
+        Some(ChunkInput::Data(&self.buffer[start..self.pos]))
+    }
+
+    /// Why the last chunk ended.
+    ///
+    /// This is only meaningful right after `read()` returned `End`.
+    pub fn boundary_kind(&self) -> BoundaryKind {
+        self.emitter.kind
+    }
+}
+
+/// Future returned by `AsyncChunkStream::read()`.
+pub struct ReadChunk<'a, R: AsyncRead + Unpin + 'a, I: ChunkerImpl + 'a> {
+    stream: Option<&'a mut AsyncChunkStream<R, I>>,
+}
+
+impl<'a, R: AsyncRead + Unpin, I: ChunkerImpl> Future for ReadChunk<'a, R, I> {
+    type Output = Option<Result<ChunkInput<'a>, ChunkError>>;
+
+    fn poll(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context,
+    ) -> Poll<Option<Result<ChunkInput<'a>, ChunkError>>> {
+        let stream = self
+            .stream
+            .take()
+            .expect("ReadChunk polled after completion");
+        if stream.emitter.end_at_split() {
+            return Poll::Ready(Some(Ok(ChunkInput::End)));
+        }
+        match stream.poll_fill(cx) {
+            Poll::Pending => {
+                self.stream = Some(stream);
+                Poll::Pending
+            }
+            Poll::Ready(Err(e)) => {
+                Poll::Ready(Some(Err(stream.emitter.error(e))))
+            }
+            Poll::Ready(Ok(())) => Poll::Ready(stream.emit().map(Ok)),
+        }
+    }
+}
+
+pub struct AsyncWholeChunks<R: AsyncRead + Unpin, I: ChunkerImpl> {
+    stream: AsyncChunkStream<R, I>,
+    buffer: Vec<u8>,
+}
+
+impl<R: AsyncRead + Unpin, I: ChunkerImpl> Stream for AsyncWholeChunks<R, I> {
+    type Item = Result<Vec<u8>, ChunkError>;
+
+    fn poll_next(
+        self: Pin<&mut Self>,
+        cx: &mut Context,
+    ) -> Poll<Option<Result<Vec<u8>, ChunkError>>> {
+        let this = self.get_mut();
+        loop {
+            match this.stream.poll_read(cx) {
+                Poll::Pending => return Poll::Pending,
+                Poll::Ready(None) => return Poll::Ready(None),
+                Poll::Ready(Some(Err(e))) => return Poll::Ready(Some(Err(e))),
+                Poll::Ready(Some(Ok(ChunkInput::Data(d)))) => {
+                    this.buffer.extend_from_slice(d)
+                }
+                Poll::Ready(Some(Ok(ChunkInput::End))) => {
+                    let mut res = Vec::new();
+                    swap(&mut res, &mut this.buffer);
+                    return Poll::Ready(Some(Ok(res)));
+                }
+            }
+        }
+    }
+}
+
+pub struct AsyncChunkInfoStream<R: AsyncRead + Unpin, I: ChunkerImpl> {
+    stream: AsyncChunkStream<R, I>,
+    last_chunk: u64,
+    pos: u64,
+}
+
+impl<R: AsyncRead + Unpin, I: ChunkerImpl> Stream
+    for AsyncChunkInfoStream<R, I>
+{
+    type Item = Result<ChunkInfo, ChunkError>;
+
+    fn poll_next(
+        self: Pin<&mut Self>,
+        cx: &mut Context,
+    ) -> Poll<Option<Result<ChunkInfo, ChunkError>>> {
+        let this = self.get_mut();
+        loop {
+            match this.stream.poll_read(cx) {
+                Poll::Pending => return Poll::Pending,
+                Poll::Ready(None) => return Poll::Ready(None),
+                Poll::Ready(Some(Err(e))) => return Poll::Ready(Some(Err(e))),
+                Poll::Ready(Some(Ok(ChunkInput::Data(d)))) => {
+                    this.pos += d.len() as u64
+                }
+                Poll::Ready(Some(Ok(ChunkInput::End))) => {
+                    let start = this.last_chunk;
+                    this.last_chunk = this.pos;
+                    return Poll::Ready(Some(Ok(ChunkInfo {
+                        start,
+                        length: this.pos - start,
+                        kind: this.stream.emitter.kind,
+                    })));
+                }
+            }
+        }
+    }
+}
+
+/// Adapter to use a tokio `AsyncRead` with the asynchronous chunk iterators.
+///
+/// ```
+/// # extern crate cdchunking;
+/// # use cdchunking::{Chunker, TokioCompat, ZPAQ};
+/// # let chunker = Chunker::new(ZPAQ::new(13));
+/// # let tokio_reader: &[u8] = b"abcdefghijklmnopqrstuvwxyz1234567890";
+/// let chunks = chunker.async_whole_chunks(TokioCompat::new(tokio_reader));
+/// ```
+#[cfg(feature = "tokio")]
+pub struct TokioCompat<R>(R);
+
+#[cfg(feature = "tokio")]
+impl<R> TokioCompat<R> {
+    /// Wraps a tokio reader.
+    pub fn new(reader: R) -> TokioCompat<R> {
+        TokioCompat(reader)
+    }
+
+    /// Returns the wrapped reader.
+    pub fn into_inner(self) -> R {
+        self.0
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl<R: ::tokio::io::AsyncRead + Unpin> AsyncRead for TokioCompat<R> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let mut buf = ::tokio::io::ReadBuf::new(buf);
+        match Pin::new(&mut self.0).poll_read(cx, &mut buf) {
+            Poll::Pending => Poll::Pending,
+            Poll::Ready(Err(e)) => Poll::Ready(Err(e)),
+            Poll::Ready(Ok(())) => Poll::Ready(Ok(buf.filled().len())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures_core::Stream;
+    use futures_io::AsyncRead;
+    use std::future::Future;
+    use std::io;
+    use std::pin::Pin;
+    use std::sync::Arc;
+    use std::task::{Context, Poll, Wake, Waker};
+
+    use super::super::tests::base;
+    use super::super::ChunkInput;
+
+    struct NoopWaker;
+
+    impl Wake for NoopWaker {
+        fn wake(self: Arc<Self>) {}
+    }
+
+    fn block_on<F: Future>(future: F) -> F::Output {
+        let waker = Waker::from(Arc::new(NoopWaker));
+        let mut cx = Context::from_waker(&waker);
+        let mut future = Box::pin(future);
+        loop {
+            if let Poll::Ready(r) = future.as_mut().poll(&mut cx) {
+                return r;
+            }
+        }
+    }
+
+    fn next<S: Stream + Unpin>(stream: &mut S) -> Option<S::Item> {
+        let waker = Waker::from(Arc::new(NoopWaker));
+        let mut cx = Context::from_waker(&waker);
+        loop {
+            if let Poll::Ready(r) = Pin::new(&mut *stream).poll_next(&mut cx) {
+                return r;
+            }
+        }
+    }
+
+    /// Reader that is not ready every other time it is polled.
+    struct SlowReader<'a>(&'a [u8], bool);
+
+    impl<'a> AsyncRead for SlowReader<'a> {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context,
+            buf: &mut [u8],
+        ) -> Poll<io::Result<usize>> {
+            self.1 = !self.1;
+            if self.1 {
+                cx.waker().wake_by_ref();
+                return Poll::Pending;
+            }
+            let len = buf.len().min(3).min(self.0.len());
+            buf[..len].copy_from_slice(&self.0[..len]);
+            self.0 = &self.0[len..];
+            Poll::Ready(Ok(len))
+        }
+    }
+
+    #[test]
+    fn test_async_stream() {
+        let (chunker, data, _, expected) = base();
+        let mut result = Vec::new();
+
+        let mut chunk_iter = chunker.async_stream(SlowReader(data, false));
+        while let Some(chunk) = block_on(chunk_iter.read()) {
+            match chunk.unwrap() {
+                ChunkInput::Data(d) => result.extend(d),
+                ChunkInput::End => result.push(b'|'),
+            }
+        }
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn test_async_whole_chunks() {
+        let (chunker, data, _, expected) = base();
+        let mut result = Vec::new();
+
+        let mut chunks = chunker.async_whole_chunks(SlowReader(data, false));
+        while let Some(chunk) = next(&mut chunks) {
+            result.extend(chunk.unwrap());
+            result.push(b'|');
+        }
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn test_async_chunks() {
+        let (chunker, data, _, _) = base();
+        let mut result = Vec::new();
+
+        let mut chunks = chunker.async_chunks(SlowReader(data, false));
+        while let Some(chunk_info) = next(&mut chunks) {
+            let chunk_info = chunk_info.unwrap();
+            result.push((chunk_info.start(), chunk_info.length()));
+        }
+        assert_eq!(
+            result,
+            vec![
+                (0, 3), (3, 5), (8, 4), (12, 2),
+                (14, 6), (20, 6), (26, 7),
+            ]
+        );
+    }
+
+    #[cfg(feature = "tokio")]
+    #[test]
+    fn test_tokio_compat() {
+        use super::TokioCompat;
+
+        let (chunker, data, _, expected) = base();
+        let mut result = Vec::new();
+
+        let mut chunks = chunker.async_whole_chunks(TokioCompat::new(data));
+        while let Some(chunk) = next(&mut chunks) {
+            result.extend(chunk.unwrap());
+            result.push(b'|');
+        }
+        assert_eq!(result, expected);
+    }
+}
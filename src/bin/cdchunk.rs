@@ -0,0 +1,559 @@
+//! Command-line tool splitting files into content-defined chunks.
+
+extern crate cdchunking;
+extern crate sha2;
+
+use cdchunking::{
+    estimate_dedup, Algorithm, BoundaryKind, ChunkInput, Chunker,
+    ChunkerBuilder, ChunkerImpl,
+};
+use sha2::{Digest, Sha256};
+use std::env;
+use std::ffi::OsString;
+use std::fmt::Write as FmtWrite;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Read, Write};
+use std::iter::Peekable;
+use std::path::{Path, PathBuf};
+use std::process;
+
+const USAGE: &str = "\
+Usage: cdchunk [OPTIONS] [FILE]...
+       cdchunk dedup [OPTIONS] [PATH]...
+
+Splits files (or the standard input, if no file is given or for `-`) into
+content-defined chunks, and prints one line per chunk with the file name, the
+offset, the length and the reason for the boundary (`natural`, `max_size` or
+`end`), separated by tabs. Backslashes, tabs and line breaks in file names
+are written as `\\\\`, `\\t`, `\\n` and `\\r`.
+
+With `dedup`, chunks all the files under the given paths (default: the current
+directory) and reports how much space de-duplicating the chunks would save, and
+which chunks are repeated the most.
+
+The chunker is given as a spec: the algorithm, `zpaq` or `gear`, then
+optionally a colon and comma-separated parameters `min`, `avg` and `max` (sizes,
+with an optional `k`, `m` or `g` suffix), `bits` (an average size of 2^bits)
+and `seed`, for example `gear:min=2k,avg=8k,max=64k`.
+
+Options:
+  -s, --spec SPEC       Chunker spec (default: `zpaq:13`)
+  -a, --algorithm ALGO  Set the algorithm of the spec
+  -b, --bits N          Add `bits=N` to the spec
+  -m, --max-size SIZE   Add `max=SIZE` to the spec
+  -d, --digest          Also print the SHA-256 digest of each chunk
+  -j, --json            Print a JSON object per line instead
+  -o, --output DIR      Write each chunk to a file in DIR, named by its digest
+                        (implies --digest)
+  -n, --top N           Number of repeated chunks to list with `dedup`
+                        (default: 10)
+  -h, --help            Print this message
+";
+
+#[derive(Debug)]
+struct Options {
+    spec: ChunkerBuilder,
+    digest: bool,
+    json: bool,
+    output: Option<PathBuf>,
+    dedup: bool,
+    top: usize,
+    files: Vec<PathBuf>,
+}
+
+fn utf8(name: &str, value: OsString) -> Result<String, String> {
+    value
+        .into_string()
+        .map_err(|_| format!("Invalid value for {}", name))
+}
+
+fn parse_args<A: Iterator<Item = OsString>>(
+    args: A,
+) -> Result<Option<Options>, String> {
+    // The old options edit the spec, which is parsed at the end
+    let mut algorithm = "zpaq".to_owned();
+    let mut params = Vec::new();
+    let mut options = Options {
+        spec: ChunkerBuilder::new(Algorithm::ZPAQ),
+        digest: false,
+        json: false,
+        output: None,
+        dedup: false,
+        top: 10,
+        files: Vec::new(),
+    };
+    let mut args = args.peekable();
+    if args.peek().map(|a| a == "dedup") == Some(true) {
+        args.next();
+        options.dedup = true;
+    }
+    while let Some(arg) = args.next() {
+        // Only file names don't have to be valid UTF-8
+        let arg = match arg.into_string() {
+            Ok(arg) => arg,
+            Err(arg) => {
+                options.files.push(PathBuf::from(arg));
+                continue;
+            }
+        };
+        if arg == "--" {
+            options.files.extend(args.map(PathBuf::from));
+            break;
+        } else if arg == "-" || !arg.starts_with('-') {
+            options.files.push(PathBuf::from(arg));
+            continue;
+        }
+
+        // Options can be given as `--opt value` or `--opt=value`
+        let (name, mut inline) = match arg.find('=') {
+            Some(i) if arg.starts_with("--") => {
+                (arg[..i].to_owned(), Some(arg[i + 1..].to_owned()))
+            }
+            _ => (arg.clone(), None),
+        };
+        let mut value = |args: &mut Peekable<A>| {
+            inline
+                .take()
+                .map(OsString::from)
+                .or_else(|| args.next())
+                .ok_or_else(|| format!("Missing value for {}", name))
+        };
+        match &name[..] {
+            "-h" | "--help" => return Ok(None),
+            "-s" | "--spec" => {
+                let spec = utf8(&name, value(&mut args)?)?;
+                params.clear();
+                match spec.find(':') {
+                    Some(i) => {
+                        algorithm = spec[..i].to_owned();
+                        if !spec[i + 1..].trim().is_empty() {
+                            params.push(spec[i + 1..].to_owned());
+                        }
+                    }
+                    None => algorithm = spec,
+                }
+            }
+            "-a" | "--algorithm" => algorithm = utf8(&name, value(&mut args)?)?,
+            "-b" | "--bits" => {
+                params.push(format!("bits={}", utf8(&name, value(&mut args)?)?))
+            }
+            "-m" | "--max-size" => {
+                params.push(format!("max={}", utf8(&name, value(&mut args)?)?))
+            }
+            "-d" | "--digest" => options.digest = true,
+            "-j" | "--json" => options.json = true,
+            "-o" | "--output" => {
+                options.output = Some(PathBuf::from(value(&mut args)?))
+            }
+            "-n" | "--top" => {
+                options.top = utf8(&name, value(&mut args)?)?
+                    .parse()
+                    .map_err(|_| "Invalid number of chunks".to_owned())?
+            }
+            _ => return Err(format!("Unknown option {}", name)),
+        }
+    }
+    let spec = if params.is_empty() {
+        algorithm
+    } else {
+        format!("{}:{}", algorithm, params.join(","))
+    };
+    options.spec = spec.parse().map_err(|e| format!("{}", e))?;
+    // Catch invalid sizes now rather than for each file
+    options.spec.build().map_err(|e| format!("{}", e))?;
+    if options.dedup {
+        if options.digest || options.output.is_some() {
+            return Err("dedup doesn't print chunks".to_owned());
+        }
+        if options.files.iter().any(|f| f.as_os_str() == "-") {
+            return Err("dedup can't read the standard input".to_owned());
+        }
+        if options.files.is_empty() {
+            options.files.push(PathBuf::from("."));
+        }
+    } else if options.files.is_empty() {
+        options.files.push(PathBuf::from("-"));
+    }
+    Ok(Some(options))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    let mut s = String::with_capacity(bytes.len() * 2);
+    for b in bytes {
+        write!(s, "{:02x}", b).unwrap();
+    }
+    s
+}
+
+fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                write!(out, "\\u{:04x}", c as u32).unwrap()
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// Escapes a field of the tab-separated output, so that it can't be
+/// mistaken for a field or line separator.
+fn tsv_field(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '\\' => out.push_str("\\\\"),
+            '\t' => out.push_str("\\t"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+fn kind_name(kind: BoundaryKind) -> &'static str {
+    match kind {
+        BoundaryKind::Natural => "natural",
+        BoundaryKind::MaxSize => "max_size",
+        BoundaryKind::EndOfStream => "end",
+    }
+}
+
+struct Chunk<'a> {
+    file: &'a Path,
+    offset: u64,
+    length: u64,
+    kind: BoundaryKind,
+    digest: Option<String>,
+}
+
+fn print_chunk<W: Write>(
+    out: &mut W,
+    options: &Options,
+    chunk: &Chunk,
+) -> io::Result<()> {
+    if options.json {
+        write!(
+            out,
+            "{{\"file\": {}, \"offset\": {}, \"length\": {}, \
+             \"boundary\": \"{}\"",
+            json_string(&chunk.file.to_string_lossy()),
+            chunk.offset,
+            chunk.length,
+            kind_name(chunk.kind)
+        )?;
+        if let Some(ref digest) = chunk.digest {
+            write!(out, ", \"digest\": \"{}\"", digest)?;
+        }
+        writeln!(out, "}}")
+    } else {
+        write!(
+            out,
+            "{}\t{}\t{}\t{}",
+            tsv_field(&chunk.file.to_string_lossy()),
+            chunk.offset,
+            chunk.length,
+            kind_name(chunk.kind)
+        )?;
+        if let Some(ref digest) = chunk.digest {
+            write!(out, "\t{}", digest)?;
+        }
+        writeln!(out)
+    }
+}
+
+fn write_chunk(dir: &Path, digest: &str, data: &[u8]) -> io::Result<()> {
+    let path = dir.join(digest);
+    // Chunks with the same digest have the same content
+    if !path.exists() {
+        fs::write(path, data)?;
+    }
+    Ok(())
+}
+
+fn chunk_reader<R: Read, I: ChunkerImpl, W: Write>(
+    name: &Path,
+    reader: R,
+    chunker: Chunker<I>,
+    options: &Options,
+    out: &mut W,
+) -> io::Result<()> {
+    let hash = options.digest || options.output.is_some();
+    let mut hasher = Sha256::new();
+    let mut buffer = Vec::new();
+    let mut offset = 0;
+    let mut length = 0;
+    let mut stream = chunker.stream(reader);
+    while let Some(chunk) = stream.read() {
+        match chunk? {
+            ChunkInput::Data(d) => {
+                length += d.len() as u64;
+                if hash {
+                    hasher.update(d);
+                }
+                if options.output.is_some() {
+                    buffer.extend_from_slice(d);
+                }
+            }
+            ChunkInput::End => {
+                // Empty input gives an empty chunk
+                if length == 0 {
+                    continue;
+                }
+                let digest = if hash {
+                    Some(hex(&hasher.finalize_reset()))
+                } else {
+                    None
+                };
+                if let (Some(dir), Some(digest)) = (&options.output, &digest) {
+                    write_chunk(dir, digest, &buffer)?;
+                    buffer.clear();
+                }
+                let chunk = Chunk {
+                    file: name,
+                    offset,
+                    length,
+                    kind: stream.boundary_kind(),
+                    digest,
+                };
+                print_chunk(out, options, &chunk)?;
+                offset += length;
+                length = 0;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn chunk_files<I, F>(options: &Options, chunker: F) -> io::Result<()>
+where
+    I: ChunkerImpl,
+    F: Fn() -> Chunker<I>,
+{
+    if let Some(ref dir) = options.output {
+        fs::create_dir_all(dir)?;
+    }
+    let stdout = io::stdout();
+    let mut out = BufWriter::new(stdout.lock());
+    for name in &options.files {
+        if name.as_os_str() == "-" {
+            let stdin = io::stdin();
+            chunk_reader(name, stdin.lock(), chunker(), options, &mut out)?;
+        } else {
+            let file = File::open(name).map_err(|e| {
+                io::Error::new(e.kind(), format!("{}: {}", name.display(), e))
+            })?;
+            chunk_reader(name, file, chunker(), options, &mut out)?;
+        }
+    }
+    out.flush()
+}
+
+fn dedup<I: ChunkerImpl + Clone>(
+    options: &Options,
+    chunker: Chunker<I>,
+) -> io::Result<()> {
+    let report = estimate_dedup(&options.files, chunker)?;
+    let top = report.top_duplicates(options.top);
+    let stdout = io::stdout();
+    let mut out = BufWriter::new(stdout.lock());
+    if options.json {
+        write!(
+            out,
+            "{{\"files\": {}, \"chunks\": {}, \"unique_chunks\": {}, \
+             \"total_bytes\": {}, \"unique_bytes\": {}, \
+             \"dedup_ratio\": {:.4}, \"top\": [",
+            report.files(),
+            report.chunks(),
+            report.unique_chunks(),
+            report.total_bytes(),
+            report.unique_bytes(),
+            report.dedup_ratio()
+        )?;
+        for (i, chunk) in top.iter().enumerate() {
+            write!(
+                out,
+                "{}{{\"file\": {}, \"offset\": {}, \"length\": {}, \
+                 \"count\": {}}}",
+                if i == 0 { "" } else { ", " },
+                json_string(&chunk.path().to_string_lossy()),
+                chunk.offset(),
+                chunk.length(),
+                chunk.count()
+            )?;
+        }
+        writeln!(out, "]}}")?;
+    } else {
+        writeln!(out, "files\t{}", report.files())?;
+        writeln!(out, "chunks\t{}", report.chunks())?;
+        writeln!(out, "unique chunks\t{}", report.unique_chunks())?;
+        writeln!(out, "total bytes\t{}", report.total_bytes())?;
+        writeln!(out, "unique bytes\t{}", report.unique_bytes())?;
+        writeln!(out, "dedup ratio\t{:.4}", report.dedup_ratio())?;
+        if !top.is_empty() {
+            writeln!(out, "\ncount\tlength\tfile\toffset")?;
+        }
+        for chunk in top {
+            writeln!(
+                out,
+                "{}\t{}\t{}\t{}",
+                chunk.count(),
+                chunk.length(),
+                tsv_field(&chunk.path().to_string_lossy()),
+                chunk.offset()
+            )?;
+        }
+    }
+    out.flush()
+}
+
+fn run_with<I, F>(options: &Options, chunker: F) -> io::Result<()>
+where
+    I: ChunkerImpl + Clone,
+    F: Fn() -> Chunker<I>,
+{
+    if options.dedup {
+        dedup(options, chunker())
+    } else {
+        chunk_files(options, chunker)
+    }
+}
+
+fn run(options: &Options) -> io::Result<()> {
+    // parse_args() made sure that the spec builds
+    run_with(options, || options.spec.build().unwrap())
+}
+
+fn main() {
+    let options = match parse_args(env::args_os().skip(1)) {
+        Ok(Some(options)) => options,
+        Ok(None) => {
+            print!("{}", USAGE);
+            return;
+        }
+        Err(e) => {
+            eprintln!("cdchunk: {}\n\n{}", e, USAGE);
+            process::exit(2);
+        }
+    };
+    if let Err(e) = run(&options) {
+        // Stop quietly if our output is closed, e.g. piped to `head`
+        if e.kind() != io::ErrorKind::BrokenPipe {
+            eprintln!("cdchunk: {}", e);
+            process::exit(1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cdchunking::BoundaryKind;
+    use std::ffi::OsString;
+    use std::path::PathBuf;
+
+    use super::{json_string, parse_args, print_chunk, tsv_field, Chunk};
+
+    fn parse(args: &str) -> Result<Option<super::Options>, String> {
+        parse_args(args.split_whitespace().map(OsString::from))
+    }
+
+    fn paths(names: &[&str]) -> Vec<PathBuf> {
+        names.iter().map(PathBuf::from).collect()
+    }
+
+    #[test]
+    fn test_parse_args() {
+        let spec = |args: &str| parse(args).unwrap().unwrap().spec.to_string();
+        let options = parse("").unwrap().unwrap();
+        assert_eq!(options.spec.to_string(), "zpaq:avg=8k");
+        assert_eq!(options.files, paths(&["-"]));
+
+        let options = parse("--spec gear:min=1k,avg=4k -d -j a -- -b")
+            .unwrap()
+            .unwrap();
+        assert_eq!(options.spec.to_string(), "gear:min=1k,avg=4k");
+        assert!(options.digest && options.json);
+        assert_eq!(options.files, paths(&["a", "-b"]));
+
+        // The old options are shorthands for parts of the spec
+        assert_eq!(spec("-a gear --bits=10 -m 4096"), "gear:avg=1k,max=4k");
+        assert_eq!(
+            spec("-s gear:min=1k -a zpaq -m 64k"),
+            "zpaq:min=1k,avg=8k,max=64k"
+        );
+        assert_eq!(spec("-b 10 --spec=gear"), "gear:avg=8k");
+        assert_eq!(spec("-s zpaq: -m 64k"), "zpaq:avg=8k,max=64k");
+
+        assert!(parse("-h").unwrap().is_none());
+        assert!(parse("--bits 40").is_err());
+        assert!(parse("-s gear:avg=4k -b 10").is_err());
+        assert!(parse("-s gear:min=8k,avg=16k,max=4k").is_err());
+        assert!(parse("-a rabin").is_err());
+        assert!(parse("--algorithm").is_err());
+        assert!(parse("--frobnicate").is_err());
+
+        let options = parse("dedup -n 3 --json").unwrap().unwrap();
+        assert!(options.dedup && options.json);
+        assert_eq!(options.top, 3);
+        assert_eq!(options.files, paths(&["."]));
+        assert!(parse("dedup -d").is_err());
+        assert!(parse("dedup -").is_err());
+        assert!(!parse("a dedup").unwrap().unwrap().dedup);
+    }
+
+    #[test]
+    fn test_json_string() {
+        assert_eq!(
+            json_string("a \"b\"\\\n\x01"),
+            "\"a \\\"b\\\"\\\\\\n\\u0001\""
+        );
+    }
+
+    #[test]
+    fn test_tsv_field() {
+        assert_eq!(tsv_field("a b.txt"), "a b.txt");
+        assert_eq!(tsv_field("a\tb\\c\nd\r"), "a\\tb\\\\c\\nd\\r");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_non_utf8_path() {
+        use std::os::unix::ffi::OsStringExt;
+
+        let name = OsString::from_vec(b"a\tb\xff".to_vec());
+        let args = vec![OsString::from("-j"), name.clone()];
+        let mut options = parse_args(args.into_iter()).unwrap().unwrap();
+        assert_eq!(options.files, vec![PathBuf::from(name)]);
+
+        let chunk = Chunk {
+            file: &options.files[0],
+            offset: 0,
+            length: 3,
+            kind: BoundaryKind::EndOfStream,
+            digest: None,
+        };
+        let mut out = Vec::new();
+        print_chunk(&mut out, &options, &chunk).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "{\"file\": \"a\\tb\u{fffd}\", \"offset\": 0, \"length\": 3, \
+             \"boundary\": \"end\"}\n"
+        );
+        options.json = false;
+        let mut out = Vec::new();
+        print_chunk(&mut out, &options, &chunk).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "a\\tb\u{fffd}\t0\t3\tend\n"
+        );
+    }
+}
@@ -0,0 +1,539 @@
+//! Building chunkers from the sizes of chunks, checking the parameters.
+
+#[cfg(not(feature = "std"))]
+use alloc::boxed::Box;
+#[cfg(not(feature = "std"))]
+use alloc::string::String;
+#[cfg(not(feature = "std"))]
+use alloc::vec::Vec;
+use core::fmt;
+use core::str::FromStr;
+
+use super::{Chunker, ChunkerImpl, ConfigError, Gear, SizeLimited, ZPAQ};
+
+/// The chunking algorithms that `ChunkerBuilder` can use.
+///
+/// They parse from and display as `"zpaq"` and `"gear"`; `"fastcdc"` is also
+/// accepted for `Gear`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum Algorithm {
+    /// `ZPAQ`
+    ZPAQ,
+    /// `Gear`, which is faster
+    Gear,
+}
+
+impl FromStr for Algorithm {
+    type Err = ConfigError;
+
+    fn from_str(s: &str) -> Result<Algorithm, ConfigError> {
+        match &s.to_ascii_lowercase()[..] {
+            "zpaq" => Ok(Algorithm::ZPAQ),
+            "gear" | "fastcdc" => Ok(Algorithm::Gear),
+            _ => Err(invalid_spec(format!("unknown algorithm {:?}", s))),
+        }
+    }
+}
+
+impl fmt::Display for Algorithm {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            Algorithm::ZPAQ => "zpaq",
+            Algorithm::Gear => "gear",
+        })
+    }
+}
+
+fn invalid_spec(msg: String) -> ConfigError {
+    ConfigError::InvalidSpec(msg)
+}
+
+const SIZE_SUFFIXES: [(char, usize); 3] =
+    [('g', 1 << 30), ('m', 1 << 20), ('k', 1 << 10)];
+
+/// Parses a size, with an optional `k`, `m` or `g` suffix (powers of 1024).
+fn parse_size(s: &str) -> Result<usize, ConfigError> {
+    let lower = s.to_ascii_lowercase();
+    let (digits, multiplier) = SIZE_SUFFIXES
+        .iter()
+        .find(|&&(c, _)| lower.ends_with(c))
+        .map(|&(_, m)| (&lower[..lower.len() - 1], m))
+        .unwrap_or((&lower[..], 1));
+    digits
+        .parse::<usize>()
+        .ok()
+        .and_then(|n| n.checked_mul(multiplier))
+        .ok_or_else(|| invalid_spec(format!("invalid size {:?}", s)))
+}
+
+/// Formats a size with the largest suffix that keeps it exact.
+fn format_size(f: &mut fmt::Formatter, size: usize) -> fmt::Result {
+    for &(c, m) in SIZE_SUFFIXES.iter() {
+        if size != 0 && size % m == 0 {
+            return write!(f, "{}{}", size / m, c);
+        }
+    }
+    write!(f, "{}", size)
+}
+
+/// One of the chunking algorithms, picked at runtime.
+// There is only one per stream, no need to box the larger one
+#[allow(clippy::large_enum_variant)]
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum AnyChunker {
+    ZPAQ(ZPAQ),
+    Gear(Gear),
+}
+
+impl ChunkerImpl for AnyChunker {
+    fn find_boundary(&mut self, data: &[u8]) -> Option<usize> {
+        match *self {
+            AnyChunker::ZPAQ(ref mut c) => c.find_boundary(data),
+            AnyChunker::Gear(ref mut c) => c.find_boundary(data),
+        }
+    }
+
+    fn reset(&mut self) {
+        match *self {
+            AnyChunker::ZPAQ(ref mut c) => c.reset(),
+            AnyChunker::Gear(ref mut c) => c.reset(),
+        }
+    }
+}
+
+/// Builds a `Chunker` from an algorithm and sizes, checking them.
+///
+/// Boundaries before `min_size` are ignored, then the algorithm finds a
+/// boundary at each byte with a probability of `1 / (avg_size - min_size)`.
+/// Chunks are `min_size` bytes plus a distance following a geometric
+/// distribution, so their average size is `avg_size`. Chunks reaching
+/// `max_size` are cut there, which makes the average a bit smaller; this
+/// happens to a fraction of about
+/// `exp(-(max_size - min_size) / (avg_size - min_size))` of the chunks.
+///
+/// ```
+/// # use cdchunking::{Algorithm, ChunkerBuilder};
+/// let chunker = ChunkerBuilder::new(Algorithm::Gear)
+///     .min_size(4096)
+///     .avg_size(16384)
+///     .max_size(65536)
+///     .build()
+///     .expect("Invalid parameters");
+/// # let reader: &[u8] = b"abcdefghijklmnopqrstuvwxyz1234567890";
+/// for chunk in chunker.whole_chunks(reader) {
+///     let chunk = chunk.expect("Error reading from file");
+///     println!("{}", chunk.len());
+/// }
+/// ```
+///
+/// It can also be parsed from a spec string, like `"gear:min=2k,avg=8k"` or
+/// `"zpaq:13,max=65536"`, which is convenient in configuration files. This is
+/// the name of the algorithm, then optionally a colon and a comma-separated
+/// list of parameters, which can be empty:
+/// * `min`, `avg`, `max`: sizes, in bytes or with a `k`, `m` or `g` suffix
+///   (powers of 1024)
+/// * `bits`: sets the average size to `2^bits`; a number without a name is
+///   also the number of bits
+/// * `seed`
+///
+/// Displaying a `ChunkerBuilder` gives the canonical spec, which parses back
+/// to the same parameters.
+///
+/// ```
+/// # use cdchunking::ChunkerBuilder;
+/// let builder: ChunkerBuilder = "zpaq:13,max=65536".parse().unwrap();
+/// assert_eq!(builder.to_string(), "zpaq:avg=8k,max=64k");
+/// let chunker = builder.build_boxed().unwrap();
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ChunkerBuilder {
+    algorithm: Algorithm,
+    min_size: usize,
+    avg_size: usize,
+    max_size: Option<usize>,
+    seed: u64,
+}
+
+impl ChunkerBuilder {
+    /// Starts building a chunker with the given algorithm.
+    ///
+    /// By default, there is no minimum or maximum size, the average size is 8
+    /// KiB, and the seed is 0.
+    pub fn new(algorithm: Algorithm) -> ChunkerBuilder {
+        ChunkerBuilder {
+            algorithm,
+            min_size: 0,
+            avg_size: 8192,
+            max_size: None,
+            seed: 0,
+        }
+    }
+
+    /// Sets the minimum size of chunks (except for the last one).
+    pub fn min_size(mut self, min_size: usize) -> ChunkerBuilder {
+        self.min_size = min_size;
+        self
+    }
+
+    /// Sets the average size of chunks.
+    ///
+    /// This doesn't have to be a power of two, but has to be larger than the
+    /// minimum size.
+    pub fn avg_size(mut self, avg_size: usize) -> ChunkerBuilder {
+        self.avg_size = avg_size;
+        self
+    }
+
+    /// Sets the maximum size of chunks.
+    pub fn max_size(mut self, max_size: usize) -> ChunkerBuilder {
+        self.max_size = Some(max_size);
+        self
+    }
+
+    /// Sets the seed, changing where boundaries fall; see `ZPAQ::with_seed()`.
+    pub fn seed(mut self, seed: u64) -> ChunkerBuilder {
+        self.seed = seed;
+        self
+    }
+
+    /// Checks the parameters and creates the `Chunker`.
+    pub fn build(
+        &self,
+    ) -> Result<Chunker<SizeLimited<AnyChunker>>, ConfigError> {
+        let (min_size, avg_size) = (self.min_size, self.avg_size);
+        if avg_size < min_size.saturating_add(2) {
+            return Err(ConfigError::AverageNotAboveMin { min_size, avg_size });
+        }
+        let max_size = match self.max_size {
+            Some(0) => return Err(ConfigError::ZeroMaxSize),
+            Some(max_size) => max_size,
+            None => usize::max_value(),
+        };
+        if min_size >= max_size {
+            return Err(ConfigError::MinSizeNotBelowMax { min_size, max_size });
+        }
+        if avg_size > max_size {
+            return Err(ConfigError::AverageAboveMax { avg_size, max_size });
+        }
+        // The distance between boundaries, after the minimum size
+        let distance = avg_size - min_size;
+        let inner = match self.algorithm {
+            Algorithm::ZPAQ => AnyChunker::ZPAQ(
+                ZPAQ::try_with_average_size(distance)?.with_seed(self.seed),
+            ),
+            Algorithm::Gear => AnyChunker::Gear(
+                Gear::try_with_average_size(distance)?.with_seed(self.seed),
+            ),
+        };
+        Ok(Chunker::new(SizeLimited::with_bounds(
+            inner, min_size, max_size,
+        )))
+    }
+
+    /// Like `build()`, but boxes the chunking method.
+    ///
+    /// This is useful to store chunkers of different types together, or to
+    /// hide the type behind a trait object in your own API.
+    pub fn build_boxed(
+        &self,
+    ) -> Result<Chunker<Box<dyn ChunkerImpl + Send>>, ConfigError> {
+        let chunker = self.build()?;
+        Ok(Chunker::new(Box::new(chunker.inner)))
+    }
+}
+
+impl FromStr for ChunkerBuilder {
+    type Err = ConfigError;
+
+    fn from_str(s: &str) -> Result<ChunkerBuilder, ConfigError> {
+        let (name, params) = match s.find(':') {
+            Some(i) => (&s[..i], Some(&s[i + 1..])),
+            None => (s, None),
+        };
+        let params = params.filter(|p| !p.trim().is_empty());
+        let mut builder = ChunkerBuilder::new(name.trim().parse()?);
+        let mut seen: Vec<&str> = Vec::new();
+        for param in params.into_iter().flat_map(|p| p.split(',')) {
+            let param = param.trim();
+            let (key, value) = match param.find('=') {
+                Some(i) => (param[..i].trim(), param[i + 1..].trim()),
+                None => ("bits", param),
+            };
+            if value.is_empty() {
+                return Err(invalid_spec(format!("missing value for {}", key)));
+            }
+            // bits and avg set the same thing
+            let group = if key == "bits" { "avg" } else { key };
+            if seen.contains(&group) {
+                return Err(invalid_spec(format!("{} given twice", group)));
+            }
+            seen.push(group);
+            match key {
+                "min" => builder.min_size = parse_size(value)?,
+                "avg" => builder.avg_size = parse_size(value)?,
+                "max" => builder.max_size = Some(parse_size(value)?),
+                "bits" => {
+                    let bits: usize = value.parse().map_err(|_| {
+                        invalid_spec(format!(
+                            "invalid number of bits {:?}",
+                            value
+                        ))
+                    })?;
+                    if bits < 1 || bits > 32 {
+                        return Err(ConfigError::InvalidBits(bits));
+                    }
+                    // 2^32 doesn't fit on 32-bit platforms, but their largest
+                    // size gives the same threshold
+                    builder.avg_size =
+                        1usize.checked_shl(bits as u32).unwrap_or(!0);
+                }
+                "seed" => {
+                    builder.seed = value.parse().map_err(|_| {
+                        invalid_spec(format!("invalid seed {:?}", value))
+                    })?;
+                }
+                _ => {
+                    return Err(invalid_spec(format!(
+                        "unknown parameter {:?}",
+                        key
+                    )))
+                }
+            }
+        }
+        Ok(builder)
+    }
+}
+
+impl fmt::Display for ChunkerBuilder {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:", self.algorithm)?;
+        if self.min_size != 0 {
+            f.write_str("min=")?;
+            format_size(f, self.min_size)?;
+            f.write_str(",")?;
+        }
+        f.write_str("avg=")?;
+        format_size(f, self.avg_size)?;
+        if let Some(max_size) = self.max_size {
+            f.write_str(",max=")?;
+            format_size(f, max_size)?;
+        }
+        if self.seed != 0 {
+            write!(f, ",seed={}", self.seed)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{self, RngCore};
+
+    use super::super::{Chunker, ConfigError, Gear, ZPAQ};
+    use super::{Algorithm, AnyChunker, ChunkerBuilder};
+
+    #[test]
+    fn test_spec() {
+        let parse = |s: &str| s.parse::<ChunkerBuilder>();
+        let builder = parse("fastcdc:min=2k,avg=8k,max=64k").unwrap();
+        assert_eq!(
+            builder,
+            ChunkerBuilder::new(Algorithm::Gear)
+                .min_size(2048)
+                .avg_size(8192)
+                .max_size(65536)
+        );
+        assert_eq!(builder.to_string(), "gear:min=2k,avg=8k,max=64k");
+
+        let builder = parse("ZPAQ: 13, max=65536").unwrap();
+        assert_eq!(builder.to_string(), "zpaq:avg=8k,max=64k");
+        assert_eq!(parse("zpaq").unwrap().to_string(), "zpaq:avg=8k");
+        assert_eq!(parse("zpaq: ").unwrap().to_string(), "zpaq:avg=8k");
+        assert!(parse("gear:32").unwrap().build().is_ok());
+        assert_eq!(
+            parse("gear:avg=3000,seed=42").unwrap().to_string(),
+            "gear:avg=3000,seed=42"
+        );
+        assert_eq!(
+            parse("gear:avg=1536k,max=1g").unwrap().to_string(),
+            "gear:avg=1536k,max=1g"
+        );
+        for s in &["gear:min=2k,avg=8k,max=64k", "zpaq:avg=3000,seed=42"] {
+            let builder = parse(s).unwrap();
+            assert_eq!(parse(&builder.to_string()).unwrap(), builder);
+        }
+
+        // The boxed chunker finds the same boundaries
+        let data = b"defghijklmnopqrstuvwxyz1234567890";
+        let builder = parse("zpaq:3,max=16").unwrap();
+        let expected: Vec<&[u8]> =
+            builder.build().unwrap().slices(data).collect();
+        let boxed: Vec<&[u8]> =
+            builder.build_boxed().unwrap().slices(data).collect();
+        assert_eq!(boxed, expected);
+        let mut zpaq = ZPAQ::new(3);
+        assert_eq!(
+            Chunker::new(&mut zpaq).max_size(16).slices(data).count(),
+            expected.len()
+        );
+
+        let error = |s: &str| parse(s).err().unwrap().to_string();
+        assert_eq!(
+            error("rabin"),
+            "invalid chunker spec: unknown algorithm \"rabin\""
+        );
+        assert_eq!(
+            error("gear:foo=1"),
+            "invalid chunker spec: unknown parameter \"foo\""
+        );
+        assert_eq!(
+            error("gear:13,avg=4k"),
+            "invalid chunker spec: avg given twice"
+        );
+        assert_eq!(
+            error("gear:max=64x"),
+            "invalid chunker spec: invalid size \"64x\""
+        );
+        assert_eq!(
+            error("gear:min="),
+            "invalid chunker spec: missing value for min"
+        );
+        assert_eq!(
+            error("gear:33"),
+            "number of bits 33 is not between 1 and 32"
+        );
+        assert_eq!(
+            error("gear:64"),
+            "number of bits 64 is not between 1 and 32"
+        );
+        assert_eq!(
+            error("gear:0"),
+            "number of bits 0 is not between 1 and 32"
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(ZPAQ::try_new(0).err(), Some(ConfigError::InvalidBits(0)));
+        assert_eq!(ZPAQ::try_new(33).err(), Some(ConfigError::InvalidBits(33)));
+        assert!(ZPAQ::try_new(32).is_ok());
+        assert_eq!(Gear::try_new(0).err(), Some(ConfigError::InvalidBits(0)));
+        assert_eq!(
+            ZPAQ::try_with_average_size(1).err(),
+            Some(ConfigError::InvalidAverageSize(1))
+        );
+        assert!(Gear::try_with_average_size(3000).is_ok());
+        assert_eq!(
+            Chunker::new(ZPAQ::new(3)).try_max_size(0).err(),
+            Some(ConfigError::ZeroMaxSize)
+        );
+
+        let builder = ChunkerBuilder::new(Algorithm::ZPAQ);
+        let check = |builder: ChunkerBuilder, error| {
+            assert_eq!(builder.build().err(), Some(error));
+        };
+        check(
+            builder.clone().avg_size(1),
+            ConfigError::AverageNotAboveMin {
+                min_size: 0,
+                avg_size: 1,
+            },
+        );
+        check(
+            builder.clone().min_size(4096).avg_size(4096),
+            ConfigError::AverageNotAboveMin {
+                min_size: 4096,
+                avg_size: 4096,
+            },
+        );
+        check(builder.clone().max_size(0), ConfigError::ZeroMaxSize);
+        check(
+            builder.clone().min_size(100).max_size(100),
+            ConfigError::MinSizeNotBelowMax {
+                min_size: 100,
+                max_size: 100,
+            },
+        );
+        check(
+            builder.clone().avg_size(4096).max_size(1024),
+            ConfigError::AverageAboveMax {
+                avg_size: 4096,
+                max_size: 1024,
+            },
+        );
+        assert_eq!(
+            ConfigError::InvalidBits(0).to_string(),
+            "number of bits 0 is not between 1 and 32"
+        );
+    }
+
+    #[test]
+    fn test_average_size() {
+        let mut data = vec![0u8; 1_000_000];
+        rand::thread_rng().fill_bytes(&mut data);
+        let lengths = |chunker: Chunker<AnyChunker>| -> Vec<usize> {
+            chunker.slices(&data).map(|s| s.len()).collect()
+        };
+
+        // Powers of two give the same boundaries as the number of bits
+        assert_eq!(
+            lengths(Chunker::new(AnyChunker::ZPAQ(ZPAQ::new(10)))),
+            lengths(Chunker::new(AnyChunker::ZPAQ(ZPAQ::with_average_size(
+                1024
+            ))))
+        );
+        assert_eq!(
+            lengths(Chunker::new(AnyChunker::Gear(Gear::new(10)))),
+            lengths(Chunker::new(AnyChunker::Gear(Gear::with_average_size(
+                1024
+            ))))
+        );
+
+        // Other sizes work too
+        for inner in &[
+            AnyChunker::ZPAQ(ZPAQ::with_average_size(3000)),
+            AnyChunker::Gear(Gear::with_average_size(3000)),
+        ] {
+            let chunks = lengths(Chunker::new(inner.clone()));
+            let mean = data.len() / chunks.len();
+            assert!(mean > 2500 && mean < 3500, "mean {}", mean);
+        }
+    }
+
+    #[test]
+    fn test_build() {
+        let mut data = vec![0u8; 200_000];
+        rand::thread_rng().fill_bytes(&mut data);
+        for &algorithm in &[Algorithm::ZPAQ, Algorithm::Gear] {
+            let builder = ChunkerBuilder::new(algorithm)
+                .min_size(256)
+                .avg_size(700)
+                .max_size(2048);
+            let chunks: Vec<usize> = builder
+                .build()
+                .unwrap()
+                .slices(&data)
+                .map(|s| s.len())
+                .collect();
+            assert_eq!(chunks.iter().sum::<usize>(), data.len());
+            for &len in &chunks[..chunks.len() - 1] {
+                assert!(len >= 256 && len <= 2048);
+            }
+            let mean = data.len() / chunks.len();
+            assert!(mean > 600 && mean < 800, "mean {}", mean);
+
+            // Seed 0 is the default, other seeds move the boundaries
+            let slices = |builder: ChunkerBuilder| -> Vec<usize> {
+                let chunker = builder.build().unwrap();
+                chunker.slices(&data).map(|s| s.len()).collect()
+            };
+            assert_eq!(slices(builder.clone().seed(0)), chunks);
+            assert!(slices(builder.clone().seed(1)) != chunks);
+            assert!(slices(builder.clone().seed(2)) != slices(builder.seed(1)));
+        }
+    }
+}
@@ -0,0 +1,294 @@
+//! Estimating how much space de-duplicating files would save.
+
+use std::cmp::Reverse;
+use std::collections::hash_map::{DefaultHasher, Entry};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::hash::Hasher;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use super::{Chunker, ChunkerImpl};
+
+/// Identifies a chunk by its content, using two 64-bit hashes.
+///
+/// This is not a cryptographic digest, but collisions are unlikely enough for
+/// an estimate.
+fn chunk_key(data: &[u8]) -> (u64, u64) {
+    let mut h1 = DefaultHasher::new();
+    h1.write_u8(1);
+    h1.write(data);
+    let mut h2 = DefaultHasher::new();
+    h2.write_u8(2);
+    h2.write(data);
+    (h1.finish(), h2.finish())
+}
+
+fn with_path(path: &Path, e: io::Error) -> io::Error {
+    io::Error::new(e.kind(), format!("{}: {}", path.display(), e))
+}
+
+struct ChunkEntry {
+    file: usize,
+    offset: u64,
+    length: usize,
+    count: u64,
+}
+
+/// Result of `estimate_dedup()`.
+pub struct DedupReport {
+    files: Vec<PathBuf>,
+    chunks: HashMap<(u64, u64), ChunkEntry>,
+    total_chunks: u64,
+    total_bytes: u64,
+    unique_bytes: u64,
+}
+
+/// A chunk found multiple times, as returned by
+/// `DedupReport::top_duplicates()`.
+pub struct DuplicateChunk<'a> {
+    path: &'a Path,
+    offset: u64,
+    length: usize,
+    count: u64,
+}
+
+impl<'a> DuplicateChunk<'a> {
+    /// The file where the chunk was first found.
+    pub fn path(&self) -> &'a Path {
+        self.path
+    }
+
+    /// The offset of the chunk in that file.
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// The size of the chunk.
+    pub fn length(&self) -> usize {
+        self.length
+    }
+
+    /// How many times the chunk was found.
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// How much space storing this chunk only once saves.
+    pub fn saved_bytes(&self) -> u64 {
+        self.length as u64 * (self.count - 1)
+    }
+}
+
+impl DedupReport {
+    fn new() -> DedupReport {
+        DedupReport {
+            files: Vec::new(),
+            chunks: HashMap::new(),
+            total_chunks: 0,
+            total_bytes: 0,
+            unique_bytes: 0,
+        }
+    }
+
+    fn add_path<I: ChunkerImpl + Clone>(
+        &mut self,
+        path: &Path,
+        chunker: &I,
+    ) -> io::Result<()> {
+        // Don't follow symlinks, to avoid counting files twice
+        let metadata =
+            fs::symlink_metadata(path).map_err(|e| with_path(path, e))?;
+        if metadata.is_dir() {
+            let mut entries: Vec<PathBuf> = fs::read_dir(path)
+                .and_then(|d| d.map(|e| e.map(|e| e.path())).collect())
+                .map_err(|e| with_path(path, e))?;
+            entries.sort();
+            for entry in entries {
+                self.add_path(&entry, chunker)?;
+            }
+        } else if metadata.is_file() {
+            self.add_file(path, chunker)
+                .map_err(|e| with_path(path, e))?;
+        }
+        Ok(())
+    }
+
+    fn add_file<I: ChunkerImpl + Clone>(
+        &mut self,
+        path: &Path,
+        chunker: &I,
+    ) -> io::Result<()> {
+        let file_index = self.files.len();
+        self.files.push(path.to_owned());
+        let chunks =
+            Chunker::new(chunker.clone()).whole_chunks(File::open(path)?);
+        let mut offset = 0;
+        for chunk in chunks {
+            let chunk = chunk?;
+            // Empty files give an empty chunk
+            if chunk.is_empty() {
+                continue;
+            }
+            self.total_chunks += 1;
+            self.total_bytes += chunk.len() as u64;
+            match self.chunks.entry(chunk_key(&chunk)) {
+                Entry::Occupied(mut e) => e.get_mut().count += 1,
+                Entry::Vacant(e) => {
+                    self.unique_bytes += chunk.len() as u64;
+                    e.insert(ChunkEntry {
+                        file: file_index,
+                        offset,
+                        length: chunk.len(),
+                        count: 1,
+                    });
+                }
+            }
+            offset += chunk.len() as u64;
+        }
+        Ok(())
+    }
+
+    /// The number of files that were read.
+    pub fn files(&self) -> usize {
+        self.files.len()
+    }
+
+    /// The total number of chunks in all the files.
+    pub fn chunks(&self) -> u64 {
+        self.total_chunks
+    }
+
+    /// The number of distinct chunks.
+    pub fn unique_chunks(&self) -> u64 {
+        self.chunks.len() as u64
+    }
+
+    /// The total size of all the files.
+    pub fn total_bytes(&self) -> u64 {
+        self.total_bytes
+    }
+
+    /// The size of the distinct chunks, i.e. the space needed to store all the
+    /// files after de-duplication.
+    pub fn unique_bytes(&self) -> u64 {
+        self.unique_bytes
+    }
+
+    /// The ratio of the total size over the de-duplicated size, or 1 if there
+    /// is no data.
+    pub fn dedup_ratio(&self) -> f64 {
+        if self.unique_bytes == 0 {
+            1.0
+        } else {
+            self.total_bytes as f64 / self.unique_bytes as f64
+        }
+    }
+
+    /// The chunks that save the most space when de-duplicated, at most `n` of
+    /// them.
+    pub fn top_duplicates(&self, n: usize) -> Vec<DuplicateChunk<'_>> {
+        let mut duplicates: Vec<DuplicateChunk> = self
+            .chunks
+            .values()
+            .filter(|e| e.count > 1)
+            .map(|e| DuplicateChunk {
+                path: &self.files[e.file],
+                offset: e.offset,
+                length: e.length,
+                count: e.count,
+            })
+            .collect();
+        duplicates
+            .sort_by_key(|d| (Reverse(d.saved_bytes()), d.path, d.offset));
+        duplicates.truncate(n);
+        duplicates
+    }
+}
+
+/// Chunks all the files in the given paths, and reports how much space
+/// de-duplicating the chunks would save.
+///
+/// Directories are walked recursively. Symbolic links and special files are
+/// skipped.
+///
+/// ```
+/// # use cdchunking::{estimate_dedup, Chunker, ZPAQ};
+/// let report = estimate_dedup(&["src"], Chunker::new(ZPAQ::new(10)))
+///     .expect("Error reading files");
+/// println!(
+///     "{} bytes, {} after de-duplication",
+///     report.total_bytes(),
+///     report.unique_bytes()
+/// );
+/// ```
+pub fn estimate_dedup<P, I>(
+    paths: &[P],
+    chunker: Chunker<I>,
+) -> io::Result<DedupReport>
+where
+    P: AsRef<Path>,
+    I: ChunkerImpl + Clone,
+{
+    let mut report = DedupReport::new();
+    for path in paths {
+        report.add_path(path.as_ref(), &chunker.inner)?;
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{self, File};
+    use std::io::Write;
+
+    use super::super::{Chunker, ZPAQ};
+    use super::estimate_dedup;
+
+    #[test]
+    fn test_estimate_dedup() {
+        let dir = ::std::env::temp_dir()
+            .join(format!("cdchunking-test-dedup-{}", ::std::process::id()));
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        let data = b"defghijklmnopqrstuvwxyz1234567890";
+        File::create(dir.join("a"))
+            .unwrap()
+            .write_all(data)
+            .unwrap();
+        File::create(dir.join("sub/b"))
+            .unwrap()
+            .write_all(&data[3..])
+            .unwrap();
+        File::create(dir.join("sub/empty")).unwrap();
+
+        let report = estimate_dedup(&[&dir], Chunker::new(ZPAQ::new(3)));
+        fs::remove_dir_all(&dir).unwrap();
+        let report = report.unwrap();
+
+        // Chunks are "def|ghijk|lmno|pq|rstuvw|xyz123|4567890", and the second
+        // file has all of them but the first one
+        assert_eq!(report.files(), 3);
+        assert_eq!(report.chunks(), 13);
+        assert_eq!(report.unique_chunks(), 7);
+        assert_eq!(report.total_bytes(), 63);
+        assert_eq!(report.unique_bytes(), 33);
+        assert!((report.dedup_ratio() - 63.0 / 33.0).abs() < 1e-9);
+
+        let top = report.top_duplicates(2);
+        assert_eq!(top.len(), 2);
+        assert_eq!(top[0].path(), dir.join("a"));
+        assert_eq!((top[0].offset(), top[0].length()), (26, 7));
+        assert_eq!(top[0].count(), 2);
+        assert_eq!(top[0].saved_bytes(), 7);
+        assert_eq!((top[1].offset(), top[1].length()), (14, 6));
+        assert_eq!(report.top_duplicates(10).len(), 6);
+    }
+
+    #[test]
+    fn test_estimate_dedup_missing() {
+        let err = estimate_dedup(&["/nonexistent"], Chunker::new(ZPAQ::new(3)))
+            .err()
+            .unwrap();
+        assert!(err.to_string().starts_with("/nonexistent: "));
+    }
+}
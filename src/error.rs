@@ -0,0 +1,210 @@
+//! Errors reading chunks, with the position where they happened.
+
+#[cfg(not(feature = "std"))]
+use alloc::string::String;
+use core::fmt;
+#[cfg(feature = "std")]
+use std::error::Error;
+#[cfg(feature = "std")]
+use std::io;
+
+/// An I/O error that happened while reading chunks.
+///
+/// This tells you where in the stream the read failed, so that you can locate
+/// the problem or resume from there. It converts into `io::Error` (keeping the
+/// `ErrorKind`), so you can still use `?` in functions returning
+/// `io::Result`.
+///
+/// ```
+/// # use cdchunking::{Chunker, ZPAQ};
+/// # let reader: &[u8] = b"abcdefghijklmnopqrstuvwxyz1234567890";
+/// for chunk in Chunker::new(ZPAQ::new(13)).whole_chunks(reader) {
+///     match chunk {
+///         Ok(chunk) => println!("{} bytes", chunk.len()),
+///         Err(e) => {
+///             eprintln!(
+///                 "Error at offset {} (chunk {}): {}",
+///                 e.offset(),
+///                 e.chunk_index(),
+///                 e.error()
+///             );
+///             break;
+///         }
+///     }
+/// }
+/// ```
+#[cfg(feature = "std")]
+pub struct ChunkError {
+    error: io::Error,
+    offset: u64,
+    chunk_index: u64,
+    partial_len: u64,
+    partial: Option<Vec<u8>>,
+}
+
+#[cfg(feature = "std")]
+impl ChunkError {
+    pub(crate) fn new(
+        error: io::Error,
+        offset: u64,
+        chunk_index: u64,
+        partial_len: u64,
+    ) -> ChunkError {
+        ChunkError {
+            error,
+            offset,
+            chunk_index,
+            partial_len,
+            partial: None,
+        }
+    }
+
+    pub(crate) fn with_partial(mut self, partial: Vec<u8>) -> ChunkError {
+        self.partial = Some(partial);
+        self
+    }
+
+    /// The error returned by the reader.
+    pub fn error(&self) -> &io::Error {
+        &self.error
+    }
+
+    /// The kind of the error returned by the reader.
+    pub fn kind(&self) -> io::ErrorKind {
+        self.error.kind()
+    }
+
+    /// Gets back the error returned by the reader, dropping the position.
+    pub fn into_inner(self) -> io::Error {
+        self.error
+    }
+
+    /// The offset in the stream where the failed read started, i.e. how many
+    /// bytes were successfully read before.
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// The index of the chunk that was being read, counting from 0.
+    ///
+    /// This counts from the start of the stream, also after resuming from a
+    /// `ChunkerState`.
+    pub fn chunk_index(&self) -> u64 {
+        self.chunk_index
+    }
+
+    /// How much of the current chunk was read before the error.
+    ///
+    /// The chunk starts at `offset() - partial_len()`.
+    pub fn partial_len(&self) -> u64 {
+        self.partial_len
+    }
+
+    /// The data of the current chunk that was read before the error, if the
+    /// stream was holding it.
+    ///
+    /// Only `whole_chunks()` holds the data of a chunk until its end; this is
+    /// a copy of `WholeChunks::partial()`, which keeps it to continue after
+    /// the error. The other streams return `None`: `stream()` already gave
+    /// you that data as `ChunkInput::Data`, and `chunk_infos()` and
+    /// `par_chunks()` only keep the positions of chunks.
+    pub fn partial(&self) -> Option<&[u8]> {
+        self.partial.as_ref().map(|p| &p[..])
+    }
+}
+
+#[cfg(feature = "std")]
+impl fmt::Debug for ChunkError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // Leave out the partial data, which can be a whole chunk
+        f.debug_struct("ChunkError")
+            .field("error", &self.error)
+            .field("offset", &self.offset)
+            .field("chunk_index", &self.chunk_index)
+            .field("partial_len", &self.partial_len)
+            .finish()
+    }
+}
+
+#[cfg(feature = "std")]
+impl fmt::Display for ChunkError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} (at offset {}, in chunk {} after {} bytes)",
+            self.error, self.offset, self.chunk_index, self.partial_len
+        )
+    }
+}
+
+#[cfg(feature = "std")]
+impl Error for ChunkError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<ChunkError> for io::Error {
+    fn from(e: ChunkError) -> io::Error {
+        io::Error::new(e.kind(), e)
+    }
+}
+
+/// Invalid parameters for a chunker.
+///
+/// This is returned by the fallible constructors, like `ZPAQ::try_new()` and
+/// `ChunkerBuilder::build()`, so that parameters coming from user input can be
+/// checked without panicking.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ConfigError {
+    /// The number of bits is not between 1 and 32.
+    InvalidBits(usize),
+    /// The average size is not between 2 and 2^32.
+    InvalidAverageSize(usize),
+    /// The maximum size is 0.
+    ZeroMaxSize,
+    /// The minimum size is not smaller than the maximum size.
+    MinSizeNotBelowMax { min_size: usize, max_size: usize },
+    /// The average size is larger than the maximum size.
+    AverageAboveMax { avg_size: usize, max_size: usize },
+    /// The average size is not larger than the minimum size.
+    AverageNotAboveMin { min_size: usize, avg_size: usize },
+    /// A chunker spec string could not be parsed.
+    InvalidSpec(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ConfigError::InvalidBits(nbits) => {
+                write!(f, "number of bits {} is not between 1 and 32", nbits)
+            }
+            ConfigError::InvalidAverageSize(avg_size) => {
+                write!(f, "average size {} is not between 2 and 2^32", avg_size)
+            }
+            ConfigError::ZeroMaxSize => write!(f, "maximum size is 0"),
+            ConfigError::MinSizeNotBelowMax { min_size, max_size } => write!(
+                f,
+                "minimum size {} is not below maximum size {}",
+                min_size, max_size
+            ),
+            ConfigError::AverageAboveMax { avg_size, max_size } => write!(
+                f,
+                "average size {} is above maximum size {}",
+                avg_size, max_size
+            ),
+            ConfigError::AverageNotAboveMin { min_size, avg_size } => write!(
+                f,
+                "average size {} is not above minimum size {}",
+                avg_size, min_size
+            ),
+            ConfigError::InvalidSpec(ref msg) => {
+                write!(f, "invalid chunker spec: {}", msg)
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl Error for ConfigError {}
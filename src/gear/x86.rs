@@ -0,0 +1,137 @@
+//! SIMD versions of the multi-lane scan, for x86_64.
+//!
+//! The kernels are `unsafe` because they use instructions that the CPU might
+//! not support; `select_scan()` only picks them after checking.
+
+#![allow(unsafe_code)]
+
+use std::arch::x86_64::*;
+
+use super::{first_hit, lane_starts, GEAR, LANES, WARMUP};
+
+fn gear(window: &[u8], pos: usize) -> i64 {
+    GEAR[window[pos] as usize] as i64
+}
+
+/// Scans the lanes two by two in SSE2 registers.
+pub fn scan_sse2(
+    window: &[u8],
+    h: u64,
+    threshold: u32,
+    salt: u32,
+) -> (Option<usize>, u64) {
+    // SSE2 is always available on x86_64
+    unsafe { scan_sse2_impl(window, h, threshold, salt) }
+}
+
+#[target_feature(enable = "sse2")]
+unsafe fn scan_sse2_impl(
+    window: &[u8],
+    h: u64,
+    threshold: u32,
+    salt: u32,
+) -> (Option<usize>, u64) {
+    let w = window.len() / LANES;
+    let s = lane_starts(w);
+    let mut h01 = _mm_set_epi64x(0, h as i64);
+    let mut h23 = _mm_setzero_si128();
+    // There is no unsigned comparison, flip the sign bits (and apply the
+    // salt at the same time)
+    let bias = _mm_set1_epi32((0x8000_0000 ^ salt) as i32);
+    let thr = _mm_set1_epi32((threshold ^ 0x8000_0000) as i32);
+    let mut hits = [None; LANES];
+    for t in 0..w + WARMUP {
+        let g01 =
+            _mm_set_epi64x(gear(window, s[1] + t), gear(window, s[0] + t));
+        let g23 =
+            _mm_set_epi64x(gear(window, s[3] + t), gear(window, s[2] + t));
+        h01 = _mm_add_epi64(_mm_slli_epi64(h01, 1), g01);
+        h23 = _mm_add_epi64(_mm_slli_epi64(h23, 1), g23);
+        let top01 = _mm_xor_si128(_mm_srli_epi64(h01, 32), bias);
+        let top23 = _mm_xor_si128(_mm_srli_epi64(h23, 32), bias);
+        // Only look at the low 32 bits of each 64-bit lane
+        let m01 = _mm_movemask_epi8(_mm_cmplt_epi32(top01, thr)) & 0x0101;
+        let m23 = _mm_movemask_epi8(_mm_cmplt_epi32(top23, thr)) & 0x0101;
+        if m01 & 0x0001 != 0 {
+            return (Some(t), _mm_cvtsi128_si64(h01) as u64);
+        }
+        if t >= WARMUP && (m01 | m23) != 0 {
+            let lanes = [
+                _mm_cvtsi128_si64(h01) as u64,
+                _mm_cvtsi128_si64(_mm_unpackhi_epi64(h01, h01)) as u64,
+                _mm_cvtsi128_si64(h23) as u64,
+                _mm_cvtsi128_si64(_mm_unpackhi_epi64(h23, h23)) as u64,
+            ];
+            let mask = [0, m01 & 0x0100, m23 & 0x0001, m23 & 0x0100];
+            for k in 1..LANES {
+                if mask[k] != 0 && hits[k].is_none() {
+                    hits[k] = Some((s[k] + t, lanes[k]));
+                }
+            }
+        }
+    }
+    let end_h = _mm_cvtsi128_si64(_mm_unpackhi_epi64(h23, h23)) as u64;
+    first_hit(&hits, end_h)
+}
+
+/// Scans the 4 lanes at once in an AVX2 register.
+///
+/// Only use this after checking that the CPU supports AVX2.
+pub fn scan_avx2(
+    window: &[u8],
+    h: u64,
+    threshold: u32,
+    salt: u32,
+) -> (Option<usize>, u64) {
+    // This is only selected if AVX2 was detected at runtime
+    unsafe { scan_avx2_impl(window, h, threshold, salt) }
+}
+
+#[target_feature(enable = "avx2")]
+unsafe fn lane(v: __m256i, k: usize) -> u64 {
+    (match k {
+        0 => _mm256_extract_epi64(v, 0),
+        1 => _mm256_extract_epi64(v, 1),
+        2 => _mm256_extract_epi64(v, 2),
+        _ => _mm256_extract_epi64(v, 3),
+    }) as u64
+}
+
+#[target_feature(enable = "avx2")]
+unsafe fn scan_avx2_impl(
+    window: &[u8],
+    h: u64,
+    threshold: u32,
+    salt: u32,
+) -> (Option<usize>, u64) {
+    let w = window.len() / LANES;
+    let s = lane_starts(w);
+    let mut hv = _mm256_set_epi64x(0, 0, 0, h as i64);
+    let thr = _mm256_set1_epi64x(threshold as i64);
+    let salt = _mm256_set1_epi64x(salt as i64);
+    let mut hits = [None; LANES];
+    for t in 0..w + WARMUP {
+        let g = _mm256_set_epi64x(
+            gear(window, s[3] + t),
+            gear(window, s[2] + t),
+            gear(window, s[1] + t),
+            gear(window, s[0] + t),
+        );
+        hv = _mm256_add_epi64(_mm256_slli_epi64(hv, 1), g);
+        // The top 32 bits fit in a signed 64-bit comparison
+        let top = _mm256_xor_si256(_mm256_srli_epi64(hv, 32), salt);
+        let below = _mm256_cmpgt_epi64(thr, top);
+        let mask = _mm256_movemask_pd(_mm256_castsi256_pd(below));
+        if mask & 1 != 0 {
+            return (Some(t), lane(hv, 0));
+        }
+        if t >= WARMUP && mask != 0 {
+            for k in 1..LANES {
+                if mask & (1 << k) != 0 && hits[k].is_none() {
+                    hits[k] = Some((s[k] + t, lane(hv, k)));
+                }
+            }
+        }
+    }
+    first_hit(&hits, lane(hv, LANES - 1))
+}
@@ -114,15 +114,173 @@
 //!     }
 //! }
 //! ```
-
-#![forbid(unsafe_code)]
-
+//!
+//! ### From a buffered reader: streaming chunks with zero copies
+//!
+//! If your input already implements `BufRead` (for example a `BufReader`, a
+//! `Cursor` or a `&[u8]`), `stream()` would still copy everything into its own
+//! buffer. Use the `stream_bufread()` method instead to get the data straight
+//! from the reader's buffer. It is used the same way as `stream()`:
+//!
+//! ```
+//! # use cdchunking::{Chunker, ChunkInput, ZPAQ};
+//! # let chunker = Chunker::new(ZPAQ::new(13));
+//! # let reader: &[u8] = b"abcdefghijklmnopqrstuvwxyz1234567890";
+//! let mut chunk_iterator = chunker.stream_bufread(reader);
+//! while let Some(chunk) = chunk_iterator.read() {
+//!     match chunk.unwrap() {
+//!         ChunkInput::Data(d) => print!("{:?}, ", d),
+//!         ChunkInput::End => println!(" end of chunk"),
+//!     }
+//! }
+//! ```
+//!
+//! ### Asynchronous reading
+//!
+//! With the `futures` feature enabled, the `async_stream()`,
+//! `async_whole_chunks()` and `async_chunks()` methods provide the same
+//! iterators over a `futures::io::AsyncRead`, as futures and `Stream`s. Enable
+//! the `tokio` feature and wrap your reader in `TokioCompat` to use tokio's
+//! `AsyncRead` instead.
+//!
+//! ### From a file: memory-mapping
+//!
+//! With the `memmap` feature enabled, the `slices_from_file()` method maps a
+//! file in memory and gives you slices of it, like `slices()` would. This
+//! uses unsafe code, as do the SSE2 and AVX2 kernels of `Gear` that the
+//! `simd` feature enables; without these two features, the crate has none.
+//!
+//! ### Parallel chunking
+//!
+//! With the `rayon` feature enabled, the `par_slices()` and `par_chunks()`
+//! methods return the exact same chunks as `slices()` and `chunks()`, but
+//! search for boundaries on multiple threads, using the rayon thread pool.
+//! `par_chunks()` still reads sequentially, a large block at a time, so any
+//! `Read` object works. This only pays off on large inputs.
+//!
+//! ### Serialization
+//!
+//! With the `serde` feature enabled, `ChunkInfo`, `BoundaryKind` and the
+//! chunker configuration (`ChunkerBuilder`, and the parameters of `ZPAQ`,
+//! `Gear` and `SizeLimited`, not their state) implement `Serialize` and
+//! `Deserialize`.
+//!
+//! ### Without the standard library
+//!
+//! The `std` feature is enabled by default. Without it, the crate is
+//! `no_std`, using only `core` and `alloc`: you can still use `ZPAQ`, `Gear`,
+//! `SizeLimited`, `ChunkerBuilder`, `slices()` and `incremental()`, but not
+//! the methods reading from a `Read` object, nor the statistics, saved
+//! states and de-duplication estimates. This requires Rust 1.36.
+//!
+//! ```toml
+//! cdchunking = { version = "2.0", default-features = false }
+//! ```
+//!
+//! ### WebAssembly
+//!
+//! With the `wasm-bindgen` feature enabled, `WasmChunker` is exported to
+//! JavaScript as `Chunker`. It is built from a `ChunkerBuilder` spec, so the
+//! boundaries match those found by Rust code using the same spec, and it
+//! returns the end offsets of the chunks in the data you push to it, and
+//! optionally their SHA-256 digests.
+
+#![cfg_attr(
+    not(any(feature = "memmap", feature = "simd")),
+    forbid(unsafe_code)
+)]
+#![cfg_attr(any(feature = "memmap", feature = "simd"), deny(unsafe_code))]
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+
+#[cfg(not(feature = "std"))]
+#[macro_use]
+extern crate alloc;
+#[cfg(any(feature = "std", test))]
+extern crate core;
+
+#[cfg(feature = "futures")]
+extern crate futures_core;
+#[cfg(feature = "futures")]
+extern crate futures_io;
+#[cfg(feature = "memmap")]
+extern crate memmap2;
 #[cfg(test)]
 extern crate rand;
-
-use std::io::{self, Read};
+#[cfg(feature = "rayon")]
+extern crate rayon_crate as rayon;
+#[cfg(feature = "serde")]
+#[macro_use]
+extern crate serde;
+#[cfg(all(test, feature = "serde"))]
+extern crate serde_json;
+#[cfg(feature = "wasm-bindgen")]
+extern crate sha2;
+#[cfg(feature = "tokio")]
+extern crate tokio_crate as tokio;
+#[cfg(feature = "wasm-bindgen")]
+extern crate wasm_bindgen_crate as wasm_bindgen;
+#[cfg(all(test, feature = "wasm-bindgen", target_arch = "wasm32"))]
+extern crate wasm_bindgen_test;
+
+#[cfg(not(feature = "std"))]
+use alloc::boxed::Box;
+use core::num::Wrapping;
+#[cfg(feature = "std")]
+use std::cmp::min;
+#[cfg(feature = "std")]
+use std::io::{self, BufRead, Read, Write};
+#[cfg(feature = "std")]
 use std::mem::swap;
-use std::num::Wrapping;
+
+#[cfg(feature = "futures")]
+mod async_io;
+mod builder;
+#[cfg(feature = "std")]
+mod dedup;
+mod error;
+mod gear;
+#[cfg(feature = "memmap")]
+mod mmap;
+#[cfg(feature = "rayon")]
+mod parallel;
+#[cfg(feature = "std")]
+mod resilience;
+#[cfg(feature = "std")]
+mod state;
+#[cfg(feature = "std")]
+mod stats;
+#[cfg(all(test, feature = "std"))]
+mod testing;
+#[cfg(feature = "wasm-bindgen")]
+mod wasm;
+#[cfg(feature = "std")]
+mod xorshift;
+
+#[cfg(feature = "tokio")]
+pub use async_io::TokioCompat;
+pub use builder::{Algorithm, AnyChunker, ChunkerBuilder};
+#[cfg(feature = "std")]
+pub use dedup::{estimate_dedup, DedupReport, DuplicateChunk};
+#[cfg(feature = "std")]
+pub use error::ChunkError;
+pub use error::ConfigError;
+pub use gear::Gear;
+#[cfg(feature = "memmap")]
+pub use mmap::FileSlices;
+#[cfg(feature = "rayon")]
+pub use parallel::ParChunks;
+#[cfg(feature = "std")]
+pub use resilience::ResilienceReport;
+#[cfg(feature = "std")]
+pub use state::{ChunkerState, SaveState};
+#[cfg(feature = "std")]
+pub use stats::ChunkStats;
+#[cfg(feature = "wasm-bindgen")]
+pub use wasm::{WasmChunker, WasmChunks};
+#[cfg(feature = "futures")]
+pub use async_io::{
+    AsyncChunkInfoStream, AsyncChunkStream, AsyncWholeChunks, ReadChunk,
+};
 
 /// This class is the internal method of finding chunk boundaries.
 ///
@@ -140,13 +298,60 @@ pub trait ChunkerImpl {
     /// included in the current chunk.
     fn find_boundary(&mut self, data: &[u8]) -> Option<usize>;
 
+    /// Like `find_boundary()`, but also tells why the boundary was set.
+    ///
+    /// The default implementation reports every boundary as `Natural`;
+    /// wrappers that force boundaries, like `SizeLimited`, override it.
+    fn find_boundary_kind(
+        &mut self,
+        data: &[u8],
+    ) -> Option<(usize, BoundaryKind)> {
+        self.find_boundary(data).map(|p| (p, BoundaryKind::Natural))
+    }
+
     /// Reset the internal state after a chunk has been emitted
     fn reset(&mut self) {}
 }
 
-#[cfg(not(test))]
+impl<I: ChunkerImpl + ?Sized> ChunkerImpl for &mut I {
+    fn find_boundary(&mut self, data: &[u8]) -> Option<usize> {
+        (**self).find_boundary(data)
+    }
+
+    fn find_boundary_kind(
+        &mut self,
+        data: &[u8],
+    ) -> Option<(usize, BoundaryKind)> {
+        (**self).find_boundary_kind(data)
+    }
+
+    fn reset(&mut self) {
+        (**self).reset()
+    }
+}
+
+/// This allows using `Chunker<Box<dyn ChunkerImpl>>`, to pick the chunking
+/// method at runtime (see `ChunkerBuilder::build_boxed()`).
+impl<I: ChunkerImpl + ?Sized> ChunkerImpl for Box<I> {
+    fn find_boundary(&mut self, data: &[u8]) -> Option<usize> {
+        (**self).find_boundary(data)
+    }
+
+    fn find_boundary_kind(
+        &mut self,
+        data: &[u8],
+    ) -> Option<(usize, BoundaryKind)> {
+        (**self).find_boundary_kind(data)
+    }
+
+    fn reset(&mut self) {
+        (**self).reset()
+    }
+}
+
+#[cfg(all(feature = "std", not(test)))]
 const BUF_SIZE: usize = 4096;
-#[cfg(test)]
+#[cfg(all(feature = "std", test))]
 const BUF_SIZE: usize = 8;
 
 /// Chunker object, wraps the rolling hash into a stream-splitting object.
@@ -160,7 +365,12 @@ impl<I: ChunkerImpl> Chunker<I> {
         Chunker { inner }
     }
 
+    #[cfg(feature = "std")]
     /// Iterates on whole chunks from a file, read into new vectors.
+    ///
+    /// If reading fails, the error is returned, and you can keep iterating to
+    /// retry; the data read so far is kept and the chunk continues. You can
+    /// also get that data with `WholeChunks::take_partial()`.
     pub fn whole_chunks<R: Read>(self, reader: R) -> WholeChunks<R, I> {
         WholeChunks {
             stream: self.stream(reader),
@@ -168,6 +378,7 @@ impl<I: ChunkerImpl> Chunker<I> {
         }
     }
 
+    #[cfg(feature = "std")]
     /// Reads all the chunks at once, in a vector of chunks (also vectors).
     ///
     /// This is similar to `.whole_chunks().collect()`, but takes care of the IO
@@ -177,12 +388,13 @@ impl<I: ChunkerImpl> Chunker<I> {
         for chunk in self.whole_chunks(reader) {
             match chunk {
                 Ok(chunk) => chunks.push(chunk),
-                Err(e) => return Err(e),
+                Err(e) => return Err(e.into()),
             }
         }
         Ok(chunks)
     }
 
+    #[cfg(feature = "std")]
     /// Reads chunks with zero allocations.
     ///
     /// This streaming iterator provides you with the chunk from an internal
@@ -212,14 +424,52 @@ impl<I: ChunkerImpl> Chunker<I> {
     pub fn stream<R: Read>(self, reader: R) -> ChunkStream<R, I> {
         ChunkStream {
             reader,
-            inner: self.inner,
+            emitter: Emitter::new(self.inner),
             buffer: [0u8; BUF_SIZE],
             pos: 0,
             len: 0,
-            status: EmitStatus::Data,
         }
     }
 
+    #[cfg(feature = "std")]
+    /// Reads chunks with zero copies from a buffered reader.
+    ///
+    /// This works like `stream()`, but instead of copying the input into an
+    /// internal buffer, boundaries are searched for directly in the buffer of
+    /// the `BufRead` object, and you get slices of it. This saves a copy of
+    /// every byte if your input is already buffered (`BufReader`, `&[u8]`,
+    /// `Cursor`, ...).
+    ///
+    /// Example:
+    ///
+    /// ```
+    /// # use cdchunking::{Chunker, ChunkInput, ZPAQ};
+    /// # let chunker = Chunker::new(ZPAQ::new(13));
+    /// # let reader: &[u8] = b"abcdefghijklmnopqrstuvwxyz1234567890";
+    /// let mut chunk_iterator = chunker.stream_bufread(reader);
+    /// while let Some(chunk) = chunk_iterator.read() {
+    ///     let chunk = chunk.unwrap();
+    ///     match chunk {
+    ///         ChunkInput::Data(d) => {
+    ///             print!("{:?}, ", d);
+    ///         }
+    ///         ChunkInput::End => println!(" end of chunk"),
+    ///     }
+    /// }
+    /// ```
+    pub fn stream_bufread<R: BufRead>(
+        self,
+        reader: R,
+    ) -> BufReadChunkStream<R, I> {
+        BufReadChunkStream {
+            reader,
+            emitter: Emitter::new(self.inner),
+            available: 0,
+            consume: 0,
+        }
+    }
+
+    #[cfg(feature = "std")]
     /// Describes the chunks (don't return the data).
     ///
     /// This iterator gives you the offset and size of the chunks, but not the
@@ -238,7 +488,7 @@ impl<I: ChunkerImpl> Chunker<I> {
     /// If your data is already in memory, you can use this method instead of
     /// `whole_chunks()` to get slices referencing the buffer rather than
     /// copying it to new vectors.
-    pub fn slices(self, buffer: &[u8]) -> Slices<I> {
+    pub fn slices<'a>(self, buffer: &'a [u8]) -> Slices<'a, I> {
         Slices {
             inner: self.inner,
             buffer,
@@ -246,34 +496,159 @@ impl<I: ChunkerImpl> Chunker<I> {
         }
     }
 
+    #[cfg(feature = "std")]
+    /// Chunks the data written to the returned object.
+    ///
+    /// This is the push-based counterpart of `stream()`: instead of reading
+    /// from a `Read`, you write the data to a `ChunkWriter`, which implements
+    /// `Write`. The callback gets invoked with the same `ChunkInput` items
+    /// that `stream()` would return, and can fail the write by returning an
+    /// error.
+    ///
+    /// Nothing is buffered, and `flush()` doesn't end the current chunk; call
+    /// `finish()` when you are done writing to end the last chunk. Like with
+    /// `stream()`, if nothing was written, that is a single empty chunk.
+    ///
+    /// Example:
+    ///
+    /// ```
+    /// # use cdchunking::{Chunker, ChunkInput, ZPAQ};
+    /// # use std::io::Write;
+    /// # let chunker = Chunker::new(ZPAQ::new(13));
+    /// let mut writer = chunker.writer(|chunk| {
+    ///     match chunk {
+    ///         ChunkInput::Data(d) => print!("{:?}, ", d),
+    ///         ChunkInput::End => println!(" end of chunk"),
+    ///     }
+    ///     Ok(())
+    /// });
+    /// writer.write_all(b"abcdefghijklmnopqrstuvwxyz1234567890").unwrap();
+    /// writer.finish().unwrap();
+    /// ```
+    pub fn writer<F>(self, callback: F) -> ChunkWriter<I, F>
+    where
+        F: FnMut(ChunkInput) -> io::Result<()>,
+    {
+        ChunkWriter {
+            emitter: Emitter::new(self.inner),
+            callback,
+            pending: 0,
+        }
+    }
+
+    /// Finds boundaries in data pushed piece by piece, with no allocation.
+    ///
+    /// This is a low-level state machine, useful if you don't have a `Read`
+    /// object to give to `chunks()`. Feed it the data in pieces of any size,
+    /// and get the absolute offsets of the boundaries back (i.e. the end of
+    /// each chunk).
+    ///
+    /// Example:
+    ///
+    /// ```
+    /// # use cdchunking::{Chunker, ZPAQ};
+    /// # let chunker = Chunker::new(ZPAQ::new(13));
+    /// let mut incremental = chunker.incremental();
+    /// let pieces: [&[u8]; 2] = [b"abcdefghijklmnopqrstuvwxyz", b"1234567890"];
+    /// for piece in pieces.iter() {
+    ///     for boundary in incremental.feed(piece) {
+    ///         println!("chunk ends at {}", boundary);
+    ///     }
+    /// }
+    /// if let Some(boundary) = incremental.finish() {
+    ///     println!("last chunk ends at {}", boundary);
+    /// }
+    /// ```
+    pub fn incremental(self) -> Incremental<I> {
+        Incremental {
+            emitter: Emitter::new(self.inner),
+        }
+    }
+
     /// Returns a new `Chunker` object that will not go over a size limit.
     ///
     /// Note that the inner chunking method IS reset when a chunk boundary is
     /// emitted because of the size limit. That means that using a size limit
     /// will not only add new boundary, inside of blocks too big, it might cause
     /// the boundary after such a one to not happen anymore.
+    ///
+    /// Panics if `max` is 0; see `try_max_size()`.
     pub fn max_size(self, max: usize) -> Chunker<SizeLimited<I>> {
         assert!(max > 0);
         Chunker {
             inner: SizeLimited::new(self.inner, max),
         }
     }
+
+    /// Like `max_size()`, but returns an error instead of panicking if `max`
+    /// is 0.
+    pub fn try_max_size(
+        self,
+        max: usize,
+    ) -> Result<Chunker<SizeLimited<I>>, ConfigError> {
+        if max == 0 {
+            return Err(ConfigError::ZeroMaxSize);
+        }
+        Ok(self.max_size(max))
+    }
 }
 
+#[cfg(feature = "std")]
 pub struct WholeChunks<R: Read, I: ChunkerImpl> {
     stream: ChunkStream<R, I>,
     buffer: Vec<u8>,
 }
 
+#[cfg(feature = "std")]
+impl<R: Read, I: ChunkerImpl> WholeChunks<R, I> {
+    /// The data read so far in the current chunk.
+    ///
+    /// After an error, this is the beginning of the chunk that was being read.
+    pub fn partial(&self) -> &[u8] {
+        &self.buffer
+    }
+
+    /// Takes the data read so far in the current chunk.
+    ///
+    /// If you keep iterating after this, the next chunk returned only has the
+    /// rest of the data. Chunk boundaries are not affected, and neither is
+    /// `ChunkError::partial_len()`, which counts from the start of the chunk.
+    ///
+    /// ```
+    /// # use cdchunking::{Chunker, ZPAQ};
+    /// # let reader: &[u8] = b"abcdefghijklmnopqrstuvwxyz1234567890";
+    /// let mut chunks = Chunker::new(ZPAQ::new(13)).whole_chunks(reader);
+    /// while let Some(chunk) = chunks.next() {
+    ///     match chunk {
+    ///         Ok(chunk) => println!("{} bytes", chunk.len()),
+    ///         Err(e) => {
+    ///             let partial = chunks.take_partial();
+    ///             eprintln!("Error: {}, keeping {} bytes", e, partial.len());
+    ///             break;
+    ///         }
+    ///     }
+    /// }
+    /// ```
+    pub fn take_partial(&mut self) -> Vec<u8> {
+        let mut res = Vec::new();
+        swap(&mut res, &mut self.buffer);
+        res
+    }
+}
+
+#[cfg(feature = "std")]
 impl<R: Read, I: ChunkerImpl> Iterator for WholeChunks<R, I> {
-    type Item = io::Result<Vec<u8>>;
+    type Item = Result<Vec<u8>, ChunkError>;
 
-    fn next(&mut self) -> Option<io::Result<Vec<u8>>> {
+    fn next(&mut self) -> Option<Result<Vec<u8>, ChunkError>> {
         while let Some(chunk) = self.stream.read() {
             match chunk {
-                Err(e) => return Some(Err(e)),
+                Err(e) => {
+                    return Some(Err(e.with_partial(self.buffer.clone())))
+                }
                 Ok(ChunkInput::Data(d)) => self.buffer.extend_from_slice(d),
                 Ok(ChunkInput::End) => {
+                    // Not take_partial(), self.stream is still borrowed
                     let mut res = Vec::new();
                     swap(&mut res, &mut self.buffer);
                     return Some(Ok(res));
@@ -300,15 +675,114 @@ enum EmitStatus {
     AtSplit, // We found the end of a chunk, emitted the Data but not the End
 }
 
+/// The state machine shared by the streaming iterators.
+///
+/// Data goes through `split()`, which returns how much of it belongs in the
+/// current chunk. When that includes a boundary, the end of the chunk is
+/// emitted separately, by `end_at_split()`, after that data.
+struct Emitter<I: ChunkerImpl> {
+    inner: I,
+    status: EmitStatus,
+    kind: BoundaryKind, // Why the current chunk ends, once we know
+    offset: u64,        // How much data went through split()
+    chunk_start: u64,   // Offset where the current chunk starts
+    chunk_index: u64,   // Index of the current chunk
+}
+
+impl<I: ChunkerImpl> Emitter<I> {
+    fn new(inner: I) -> Emitter<I> {
+        Emitter {
+            inner,
+            // An empty stream is still one (empty) chunk
+            status: EmitStatus::Data,
+            kind: BoundaryKind::EndOfStream,
+            offset: 0,
+            chunk_start: 0,
+            chunk_index: 0,
+        }
+    }
+
+    /// Positions the state machine in the middle of a stream.
+    #[cfg(feature = "std")]
+    fn resume(&mut self, position: u64, last_boundary: u64, chunk_index: u64) {
+        self.offset = position;
+        self.chunk_start = last_boundary;
+        self.chunk_index = chunk_index;
+        self.status = if position > last_boundary || position == 0 {
+            EmitStatus::Data
+        } else {
+            EmitStatus::End
+        };
+    }
+
+    /// Returns how much of `data` is part of the current chunk.
+    fn split(&mut self, data: &[u8]) -> usize {
+        let len = match self.inner.find_boundary_kind(data) {
+            Some((split, kind)) => {
+                assert!(split < data.len());
+                self.status = EmitStatus::AtSplit;
+                self.kind = kind;
+                split + 1
+            }
+            None => {
+                self.status = EmitStatus::Data;
+                data.len()
+            }
+        };
+        self.offset += len as u64;
+        len
+    }
+
+    /// Ends the current chunk if `split()` found its boundary.
+    fn end_at_split(&mut self) -> bool {
+        if self.status != EmitStatus::AtSplit {
+            return false;
+        }
+        self.status = EmitStatus::End;
+        self.inner.reset();
+        self.end_chunk();
+        true
+    }
+
+    /// Ends the last chunk at the end of the input, unless it was ended
+    /// already.
+    fn end_of_stream(&mut self) -> bool {
+        if self.status != EmitStatus::Data {
+            return false;
+        }
+        self.status = EmitStatus::End;
+        self.kind = BoundaryKind::EndOfStream;
+        self.end_chunk();
+        true
+    }
+
+    fn end_chunk(&mut self) {
+        self.chunk_start = self.offset;
+        self.chunk_index += 1;
+    }
+
+    /// Adds our position in the stream to an error from the reader.
+    #[cfg(feature = "std")]
+    fn error(&self, error: io::Error) -> ChunkError {
+        ChunkError::new(
+            error,
+            self.offset,
+            self.chunk_index,
+            self.offset - self.chunk_start,
+        )
+    }
+}
+
+#[cfg(feature = "std")]
 pub struct ChunkStream<R: Read, I: ChunkerImpl> {
     reader: R,
-    inner: I,
+    emitter: Emitter<I>,
     buffer: [u8; BUF_SIZE],
     len: usize, // How much of the buffer has been read in from the reader
     pos: usize, // Where are we in handling the buffer
-    status: EmitStatus,
 }
 
+#[cfg(feature = "std")]
 impl<R: Read, I: ChunkerImpl> ChunkStream<R, I> {
     /// Iterate on the chunks, returning `ChunkInput` items.
     ///
@@ -316,88 +790,338 @@ impl<R: Read, I: ChunkerImpl> ChunkStream<R, I> {
     /// indicating the boundary between chunks.
     ///
     /// `End` is always returned at the end of the last chunk.
+    ///
+    /// Reads interrupted by a signal (`ErrorKind::Interrupted`) are retried.
+    /// Other errors are returned, with the position where they happened, but
+    /// you can keep calling `read()` afterwards: it picks up where it left
+    /// off, retrying the failed read, so no data is lost or repeated.
     // Can't be Iterator because of 'a
-    pub fn read<'a>(&'a mut self) -> Option<io::Result<ChunkInput<'a>>> {
-        if self.status == EmitStatus::AtSplit {
-            self.status = EmitStatus::End;
-            self.inner.reset();
+    pub fn read<'a>(
+        &'a mut self,
+    ) -> Option<Result<ChunkInput<'a>, ChunkError>> {
+        if self.emitter.end_at_split() {
             return Some(Ok(ChunkInput::End));
         }
         if self.pos == self.len {
-            assert!(self.status != EmitStatus::AtSplit);
-            self.pos = 0;
-            self.len = match self.reader.read(&mut self.buffer) {
-                Ok(l) => l,
-                Err(e) => return Some(Err(e)),
+            let len = loop {
+                match self.reader.read(&mut self.buffer) {
+                    Ok(l) => break l,
+                    Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                    Err(e) => return Some(Err(self.emitter.error(e))),
+                }
             };
+            self.pos = 0;
+            self.len = len;
             if self.len == 0 {
-                if self.status == EmitStatus::Data {
-                    self.status = EmitStatus::End;
+                if self.emitter.end_of_stream() {
                     return Some(Ok(ChunkInput::End));
                 }
                 return None;
             }
         }
-        if let Some(split) =
-            self.inner.find_boundary(&self.buffer[self.pos..self.len])
-        {
-            assert!(self.pos + split < self.len);
-            self.status = EmitStatus::AtSplit;
-            let start = self.pos;
-            self.pos += split + 1;
-            return Some(Ok(ChunkInput::Data(&self.buffer[start..self.pos])));
-        }
         let start = self.pos;
-        self.pos = self.len;
-        self.status = EmitStatus::Data;
-        Some(Ok(ChunkInput::Data(&self.buffer[start..self.len])))
+        self.pos += self.emitter.split(&self.buffer[start..self.len]);
+        Some(Ok(ChunkInput::Data(&self.buffer[start..self.pos])))
+    }
+
+    /// Why the last chunk ended.
+    ///
+    /// This is only meaningful right after `read()` returned `End`.
+    pub fn boundary_kind(&self) -> BoundaryKind {
+        self.emitter.kind
+    }
+}
+
+#[cfg(feature = "std")]
+pub struct BufReadChunkStream<R: BufRead, I: ChunkerImpl> {
+    reader: R,
+    emitter: Emitter<I>,
+    available: usize, // How much is left in the reader's buffer
+    consume: usize, // How much of the reader's buffer we handed out last time
+}
+
+#[cfg(feature = "std")]
+impl<R: BufRead, I: ChunkerImpl> BufReadChunkStream<R, I> {
+    /// Iterate on the chunks, returning `ChunkInput` items.
+    ///
+    /// This works like `ChunkStream::read()`, except the data is borrowed
+    /// from the reader's own buffer. It is consumed from the reader on the
+    /// next call.
+    ///
+    /// Errors are handled the same way: interrupted reads are retried, and
+    /// you can keep reading after other errors.
+    // Can't be Iterator because of 'a
+    pub fn read<'a>(
+        &'a mut self,
+    ) -> Option<Result<ChunkInput<'a>, ChunkError>> {
+        if self.consume > 0 {
+            self.reader.consume(self.consume);
+            self.available -= self.consume;
+            self.consume = 0;
+        }
+        if self.emitter.end_at_split() {
+            return Some(Ok(ChunkInput::End));
+        }
+        if self.available == 0 {
+            // Refill the reader's buffer, retrying if interrupted. The
+            // borrow checker won't let us return the buffer from this loop,
+            // so we only keep its size
+            loop {
+                match self.reader.fill_buf() {
+                    Ok(b) => {
+                        self.available = b.len();
+                        break;
+                    }
+                    Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                    Err(e) => return Some(Err(self.emitter.error(e))),
+                }
+            }
+            if self.available == 0 {
+                if self.emitter.end_of_stream() {
+                    return Some(Ok(ChunkInput::End));
+                }
+                return None;
+            }
+        }
+        // The data is already in the reader's buffer, this doesn't read
+        let buffer = match self.reader.fill_buf() {
+            Ok(b) => b,
+            Err(e) => return Some(Err(self.emitter.error(e))),
+        };
+        self.available = buffer.len();
+        self.consume = self.emitter.split(buffer);
+        Some(Ok(ChunkInput::Data(&buffer[..self.consume])))
+    }
+
+    /// Why the last chunk ended.
+    ///
+    /// This is only meaningful right after `read()` returned `End`.
+    pub fn boundary_kind(&self) -> BoundaryKind {
+        self.emitter.kind
+    }
+}
+
+#[cfg(feature = "std")]
+pub struct ChunkWriter<I: ChunkerImpl, F: FnMut(ChunkInput) -> io::Result<()>> {
+    emitter: Emitter<I>,
+    callback: F,
+    pending: usize, // Data that went through the chunker but not the callback
+}
+
+#[cfg(feature = "std")]
+impl<I: ChunkerImpl, F: FnMut(ChunkInput) -> io::Result<()>> ChunkWriter<I, F> {
+    /// Ends the last chunk, if any data was written since the last boundary.
+    ///
+    /// If nothing was written at all, this ends an empty chunk.
+    pub fn finish(mut self) -> io::Result<()> {
+        if self.emitter.end_at_split() || self.emitter.end_of_stream() {
+            (self.callback)(ChunkInput::End)?;
+        }
+        Ok(())
+    }
+
+    /// Invokes the callback for the end of a chunk, if all of its data was
+    /// passed to the callback already.
+    fn end_chunk(&mut self) -> io::Result<()> {
+        if self.pending == 0 && self.emitter.status == EmitStatus::AtSplit {
+            (self.callback)(ChunkInput::End)?;
+            self.emitter.end_at_split();
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "std")]
+impl<I: ChunkerImpl, F: FnMut(ChunkInput) -> io::Result<()>> Write
+    for ChunkWriter<I, F>
+{
+    /// Feeds the data to the chunker, invoking the callback.
+    ///
+    /// If the callback returns an error, this returns how much data it
+    /// accepted before that, or the error if that's nothing. The data it
+    /// failed on should be written again, as `write_all()` does; it is passed
+    /// to the callback again without going through the chunker twice, so the
+    /// boundaries are not affected.
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut pos = 0;
+        loop {
+            if let Err(e) = self.end_chunk() {
+                return if pos > 0 { Ok(pos) } else { Err(e) };
+            }
+            if pos == buf.len() {
+                return Ok(pos);
+            }
+            if self.pending == 0 {
+                self.pending = self.emitter.split(&buf[pos..]);
+            }
+            let len = min(self.pending, buf.len() - pos);
+            let data = ChunkInput::Data(&buf[pos..pos + len]);
+            if let Err(e) = (self.callback)(data) {
+                return if pos > 0 { Ok(pos) } else { Err(e) };
+            }
+            self.pending -= len;
+            pos += len;
+        }
+    }
+
+    /// Invokes the callback for the end of a chunk, if it failed before.
+    ///
+    /// This does NOT end the current chunk, use `finish()` for that. Wrappers
+    /// like `BufWriter` call `flush()` whenever they see fit, so ending a
+    /// chunk here would make the boundaries depend on how the data was
+    /// buffered.
+    fn flush(&mut self) -> io::Result<()> {
+        self.end_chunk()
+    }
+}
+
+pub struct Incremental<I: ChunkerImpl> {
+    emitter: Emitter<I>,
+}
+
+impl<I: ChunkerImpl> Incremental<I> {
+    /// Feeds more data, returning an iterator on the boundaries found in it.
+    ///
+    /// The data is processed as the iterator gets consumed. If it gets dropped
+    /// before the end, the rest of the data is processed then, so no boundary
+    /// is lost if you don't consume it.
+    pub fn feed<'a>(&'a mut self, data: &'a [u8]) -> Boundaries<'a, I> {
+        Boundaries {
+            incremental: self,
+            data,
+        }
+    }
+
+    /// Signals the end of the data, returning the offset of the end of the
+    /// last chunk.
+    ///
+    /// This returns `None` if no data was fed since the last boundary. If no
+    /// data was fed at all, this returns `Some(0)`: like with `chunks()`, an
+    /// empty stream is a single empty chunk.
+    pub fn finish(mut self) -> Option<u64> {
+        if self.emitter.end_of_stream() {
+            Some(self.emitter.offset)
+        } else {
+            None
+        }
+    }
+
+    /// The total amount of data fed so far.
+    pub fn position(&self) -> u64 {
+        self.emitter.offset
+    }
+}
+
+/// Iterator on the boundaries found by `Incremental::feed()`.
+pub struct Boundaries<'a, I: ChunkerImpl + 'a> {
+    incremental: &'a mut Incremental<I>,
+    data: &'a [u8],
+}
+
+impl<'a, I: ChunkerImpl> Iterator for Boundaries<'a, I> {
+    type Item = u64;
+
+    fn next(&mut self) -> Option<u64> {
+        if self.data.is_empty() {
+            return None;
+        }
+        let emitter = &mut self.incremental.emitter;
+        let split = emitter.split(self.data);
+        self.data = &self.data[split..];
+        if emitter.end_at_split() {
+            Some(emitter.offset)
+        } else {
+            None
+        }
+    }
+}
+
+impl<'a, I: ChunkerImpl> Drop for Boundaries<'a, I> {
+    fn drop(&mut self) {
+        while self.next().is_some() {}
     }
 }
 
+/// Why a chunk ended.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum BoundaryKind {
+    /// The boundary was found in the content.
+    Natural,
+    /// The chunk reached the maximum size set with `Chunker::max_size()`.
+    MaxSize,
+    /// The input ended.
+    EndOfStream,
+}
+
+/// Position of a chunk in the stream.
+///
+/// Offsets are 64-bit, so that streams larger than 4 GiB can be chunked on
+/// 32-bit platforms too.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct ChunkInfo {
-    start: usize,
-    length: usize,
+    start: u64,
+    length: u64,
+    kind: BoundaryKind,
 }
 
 impl ChunkInfo {
-    pub fn start(&self) -> usize {
+    pub fn start(&self) -> u64 {
         self.start
     }
 
-    pub fn length(&self) -> usize {
+    pub fn length(&self) -> u64 {
         self.length
     }
 
-    pub fn end(&self) -> usize {
-        self.start + self.length
+    /// The offset right after the chunk, `start() + length()`.
+    ///
+    /// This saturates at `u64::MAX`, which only matters if the `ChunkInfo`
+    /// didn't come from a chunker, e.g. if it was deserialized.
+    pub fn end(&self) -> u64 {
+        self.start.saturating_add(self.length)
+    }
+
+    /// Why this chunk ended.
+    ///
+    /// Chunks that didn't end at a `Natural` boundary would likely be cut
+    /// differently if the data around them changed, so they are less likely
+    /// to be de-duplicated.
+    pub fn kind(&self) -> BoundaryKind {
+        self.kind
     }
 }
 
+#[cfg(feature = "std")]
 pub struct ChunkInfoStream<R: Read, I: ChunkerImpl> {
     stream: ChunkStream<R, I>,
-    last_chunk: usize,
-    pos: usize,
+    last_chunk: u64,
+    pos: u64,
 }
 
+#[cfg(feature = "std")]
 impl<R: Read, I: ChunkerImpl> Iterator for ChunkInfoStream<R, I> {
-    type Item = io::Result<ChunkInfo>;
-
-    fn next(&mut self) -> Option<io::Result<ChunkInfo>> {
-        while let Some(chunk) = self.stream.read() {
-            match chunk {
-                Err(e) => return Some(Err(e)),
-                Ok(ChunkInput::Data(d)) => self.pos += d.len(),
-                Ok(ChunkInput::End) => {
-                    let start = self.last_chunk;
-                    self.last_chunk = self.pos;
-                    return Some(Ok(ChunkInfo {
-                        start,
-                        length: self.pos - start,
-                    }));
-                }
+    type Item = Result<ChunkInfo, ChunkError>;
+
+    fn next(&mut self) -> Option<Result<ChunkInfo, ChunkError>> {
+        loop {
+            match self.stream.read() {
+                None => return None,
+                Some(Err(e)) => return Some(Err(e)),
+                Some(Ok(ChunkInput::Data(d))) => self.pos += d.len() as u64,
+                // Reading the kind has to wait until the stream isn't borrowed
+                Some(Ok(ChunkInput::End)) => break,
             }
         }
-        None
+        let start = self.last_chunk;
+        self.last_chunk = self.pos;
+        Some(Ok(ChunkInfo {
+            start,
+            length: self.pos - start,
+            kind: self.stream.emitter.kind,
+        }))
     }
 }
 
@@ -435,9 +1159,13 @@ impl<'a, I: ChunkerImpl> Iterator for Slices<'a, I> {
 /// emitted because of the size limit. This will generally reduce content-dependence,
 /// and thus deduplication ratio, because the boundary is set by size rather than by
 /// content.
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct SizeLimited<I: ChunkerImpl> {
     inner: I,
+    #[cfg_attr(feature = "serde", serde(skip))]
     pos: usize,
+    min_size: usize,
     max_size: usize,
 }
 
@@ -447,6 +1175,26 @@ impl<I: ChunkerImpl> SizeLimited<I> {
         SizeLimited {
             inner,
             pos: 0,
+            min_size: 0,
+            max_size,
+        }
+    }
+
+    /// Wraps the given chunker implementation to bound the size of produced
+    /// chunks on both sides.
+    ///
+    /// Boundaries found before `min_size` bytes are ignored; the inner
+    /// chunker still sees those bytes, but is not reset. The last chunk of a
+    /// stream can still be smaller.
+    ///
+    /// Panics unless `min_size < max_size`; `ChunkerBuilder` checks this
+    /// without panicking.
+    pub fn with_bounds(inner: I, min_size: usize, max_size: usize) -> Self {
+        assert!(min_size < max_size);
+        SizeLimited {
+            inner,
+            pos: 0,
+            min_size,
             max_size,
         }
     }
@@ -454,33 +1202,40 @@ impl<I: ChunkerImpl> SizeLimited<I> {
 
 impl<I: ChunkerImpl> ChunkerImpl for SizeLimited<I> {
     fn find_boundary(&mut self, data: &[u8]) -> Option<usize> {
+        self.find_boundary_kind(data).map(|(p, _)| p)
+    }
+
+    fn find_boundary_kind(
+        &mut self,
+        data: &[u8],
+    ) -> Option<(usize, BoundaryKind)> {
         assert!(self.max_size > self.pos);
         if data.is_empty() {
             return None;
         }
         let left = self.max_size - self.pos;
-        if left == 1 {
-            Some(0)
+        let slice = if data.len() > left {
+            &data[..left]
         } else {
-            let slice = if data.len() > left {
-                &data[..left]
-            } else {
-                data
-            };
-            match self.inner.find_boundary(slice) {
-                Some(p) => {
-                    self.pos += p + 1;
-                    Some(p)
-                }
-                None => {
-                    self.pos += slice.len();
-                    if data.len() >= left {
-                        Some(left - 1)
-                    } else {
-                        None
-                    }
-                }
+            data
+        };
+        let mut start = 0;
+        while let Some((p, kind)) =
+            self.inner.find_boundary_kind(&slice[start..])
+        {
+            let end = start + p + 1;
+            if self.pos + end >= self.min_size {
+                self.pos += end;
+                return Some((end - 1, kind));
             }
+            // Too small, ignore this boundary
+            start = end;
+        }
+        self.pos += slice.len();
+        if data.len() >= left {
+            Some((left - 1, BoundaryKind::MaxSize))
+        } else {
+            None
         }
     }
 
@@ -492,6 +1247,28 @@ impl<I: ChunkerImpl> ChunkerImpl for SizeLimited<I> {
 
 const HM: Wrapping<u32> = Wrapping(123_456_791);
 
+/// Computes the threshold under which a 32-bit hash marks a boundary, to get
+/// the given average distance between boundaries.
+fn threshold_for(avg_size: usize) -> Result<u32, ConfigError> {
+    if avg_size < 2 || avg_size as u64 > 1 << 32 {
+        return Err(ConfigError::InvalidAverageSize(avg_size));
+    }
+    let avg_size = avg_size as u64;
+    Ok((((1 << 32) + avg_size / 2) / avg_size) as u32)
+}
+
+/// Turns a seed into a value to XOR with the top 32 bits of a hash.
+///
+/// Only the top bits of the hash are compared, so the seed has to be mixed
+/// into them. This is the finalizer of SplitMix64, which maps 0 to 0.
+fn salt_from_seed(seed: u64) -> u32 {
+    let mut z = seed;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^= z >> 31;
+    (z >> 32) as u32
+}
+
 /// ZPAQ-like chunking algorithm.
 ///
 /// Note that this does NOT match the official implementation (the ZPAQ C++
@@ -500,23 +1277,85 @@ const HM: Wrapping<u32> = Wrapping(123_456_791);
 /// `Chunker::max_size()` explicitly). In addition, the constants used by this
 /// implementation are different; see
 /// [#6](https://github.com/remram44/cdchunking-rs/issues/6).
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct ZPAQ {
-    nbits: usize,
+    threshold: u32, // Boundary if the hash is below this
+    salt: u32,      // XORed with the hash, to move the boundaries
+    #[cfg_attr(feature = "serde", serde(skip))]
     c1: u8, // previous byte
+    #[cfg_attr(feature = "serde", serde(skip, default = "zpaq_o1"))]
     o1: [u8; 256],
+    #[cfg_attr(feature = "serde", serde(skip, default = "zpaq_h"))]
     h: Wrapping<u32>,
 }
 
+#[cfg(feature = "serde")]
+fn zpaq_o1() -> [u8; 256] {
+    [0; 256]
+}
+
+#[cfg(feature = "serde")]
+fn zpaq_h() -> Wrapping<u32> {
+    HM
+}
+
 impl ZPAQ {
+    /// Creates a ZPAQ chunker with an average chunk size of `2^nbits`.
+    ///
+    /// Panics unless `nbits` is between 1 and 32; see `try_new()`.
     pub fn new(nbits: usize) -> ZPAQ {
+        ZPAQ::try_new(nbits).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Creates a ZPAQ chunker with an average chunk size of `2^nbits`, or
+    /// returns an error if `nbits` is not between 1 and 32.
+    pub fn try_new(nbits: usize) -> Result<ZPAQ, ConfigError> {
+        if nbits < 1 || nbits > 32 {
+            return Err(ConfigError::InvalidBits(nbits));
+        }
+        Ok(ZPAQ::with_threshold(1 << (32 - nbits)))
+    }
+
+    /// Creates a ZPAQ chunker with the given average chunk size, which
+    /// doesn't have to be a power of two.
+    ///
+    /// Panics unless `avg_size` is between 2 and 2^32; see
+    /// `try_with_average_size()`.
+    pub fn with_average_size(avg_size: usize) -> ZPAQ {
+        ZPAQ::try_with_average_size(avg_size)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Creates a ZPAQ chunker with the given average chunk size, or returns
+    /// an error if it is not between 2 and 2^32.
+    pub fn try_with_average_size(
+        avg_size: usize,
+    ) -> Result<ZPAQ, ConfigError> {
+        Ok(ZPAQ::with_threshold(threshold_for(avg_size)?))
+    }
+
+    fn with_threshold(threshold: u32) -> ZPAQ {
         ZPAQ {
-            nbits: 32 - nbits,
+            threshold,
+            salt: 0,
             c1: 0,
             o1: [0; 256],
             h: HM,
         }
     }
 
+    /// Changes where the boundaries fall, depending on a seed.
+    ///
+    /// Chunkers with different seeds cut the same data differently, which
+    /// makes it harder to guess the content of data from the size of its
+    /// chunks. This is not a cryptographic protection. A seed of 0 gives the
+    /// same boundaries as no seed.
+    pub fn with_seed(mut self, seed: u64) -> ZPAQ {
+        self.salt = salt_from_seed(seed);
+        self
+    }
+
     pub fn update(&mut self, byte: u8) -> bool {
         if byte == self.o1[self.c1 as usize] {
             self.h = self.h * HM + Wrapping(byte as u32 + 1);
@@ -526,7 +1365,7 @@ impl ZPAQ {
         self.o1[self.c1 as usize] = byte;
         self.c1 = byte;
 
-        self.h.0 < (1 << self.nbits)
+        (self.h.0 ^ self.salt) < self.threshold
     }
 }
 
@@ -550,15 +1389,19 @@ impl ChunkerImpl for ZPAQ {
     }
 }
 
-#[cfg(test)]
+#[cfg(all(test, feature = "std"))]
+#[allow(clippy::needless_borrow, clippy::type_complexity)]
 mod tests {
     use rand::{self, Rng};
-    use std::io::{self, Read};
+    use std::io::{self, BufReader, Read, Write};
     use std::str::from_utf8;
 
-    use super::{ChunkInput, Chunker, ZPAQ};
+    use super::{
+        BoundaryKind, ChunkError, ChunkInfo, ChunkInput, Chunker, ChunkerImpl,
+        ZPAQ,
+    };
 
-    fn base() -> (
+    pub fn base() -> (
         Chunker<ZPAQ>,
         &'static [u8],
         io::Cursor<&'static [u8]>,
@@ -577,7 +1420,7 @@ mod tests {
         let mut result = Vec::new();
 
         // Read whole chunks accumulated in vectors
-        for chunk /* io::Result<Vec<u8>> */ in chunker.whole_chunks(reader) {
+        for chunk /* Result<Vec<u8>, _> */ in chunker.whole_chunks(reader) {
             let chunk = chunk.unwrap();
             result.extend(chunk);
             result.push(b'|');
@@ -628,6 +1471,172 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_stream_bufread() {
+        let (chunker, _, reader, expected) = base();
+        let mut result = Vec::new();
+
+        // Zero-copy by borrowing the reader's own buffer; use a small buffer
+        // so chunks get split between reads
+        let reader = BufReader::with_capacity(5, reader);
+        let mut chunk_iter = chunker.stream_bufread(reader);
+        while let Some(chunk) = chunk_iter.read() {
+            let chunk = chunk.unwrap();
+            match chunk {
+                ChunkInput::Data(d) => {
+                    assert!(d.len() <= 5);
+                    result.extend(d);
+                }
+                ChunkInput::End => result.push(b'|'),
+            }
+        }
+        assert_eq!(
+            from_utf8(&result).unwrap(),
+            from_utf8(&expected).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_writer() {
+        let (chunker, data, _, expected) = base();
+        let mut result = Vec::new();
+
+        // Push the data in pieces that don't line up with the chunks
+        {
+            let mut writer = chunker.writer(|chunk| {
+                match chunk {
+                    ChunkInput::Data(d) => {
+                        result.extend(d);
+                    }
+                    ChunkInput::End => result.push(b'|'),
+                }
+                Ok(())
+            });
+            for piece in data.chunks(4) {
+                writer.write_all(piece).unwrap();
+            }
+            writer.finish().unwrap();
+        }
+        assert_eq!(
+            from_utf8(&result).unwrap(),
+            from_utf8(&expected).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_writer_errors() {
+        let (chunker, data, _, expected) = base();
+        let mut result = Vec::new();
+
+        // The callback fails sometimes, on data and on ends of chunks
+        let mut calls = 0;
+        {
+            let mut writer = chunker.writer(|chunk| {
+                calls += 1;
+                if [1, 2, 4, 7, 10, 11, 14].contains(&calls) {
+                    return Err(io::Error::new(io::ErrorKind::Other, "Test"));
+                }
+                match chunk {
+                    ChunkInput::Data(d) => result.extend(d),
+                    ChunkInput::End => result.push(b'|'),
+                }
+                Ok(())
+            });
+            let mut written = 0;
+            let mut partial = 0;
+            while written < data.len() {
+                match writer.write(&data[written..]) {
+                    Ok(len) => {
+                        written += len;
+                        partial += 1;
+                    }
+                    Err(e) => assert_eq!(e.kind(), io::ErrorKind::Other),
+                }
+            }
+            assert!(partial > 1);
+            while writer.flush().is_err() {}
+            writer.finish().unwrap();
+        }
+        assert_eq!(
+            from_utf8(&result).unwrap(),
+            from_utf8(&expected).unwrap()
+        );
+
+        // Like stream(), an empty stream is a single empty chunk
+        let (chunker, _, _, _) = base();
+        let mut ends = 0;
+        chunker
+            .writer(|chunk| {
+                match chunk {
+                    ChunkInput::Data(d) => assert!(d.is_empty()),
+                    ChunkInput::End => ends += 1,
+                }
+                Ok(())
+            })
+            .finish()
+            .unwrap();
+        assert_eq!(ends, 1);
+    }
+
+    #[test]
+    fn test_incremental() {
+        let (chunker, data, _, _) = base();
+        let mut result = Vec::new();
+
+        // Feed the data in pieces that don't line up with the chunks
+        let mut incremental = chunker.incremental();
+        for piece in data.chunks(4) {
+            result.extend(incremental.feed(piece));
+        }
+        // Boundaries are not lost if the iterator is not consumed
+        incremental.feed(b"ab");
+        assert_eq!(incremental.position(), 35);
+        result.extend(incremental.finish());
+        assert_eq!(result, vec![3, 8, 12, 14, 20, 26, 35]);
+
+        // Like with chunks(), an empty stream is a single empty chunk
+        let (chunker, _, _, _) = base();
+        assert_eq!(chunker.incremental().finish(), Some(0));
+    }
+
+    /// Never finds a boundary, so it can go through a lot of data quickly.
+    #[derive(Clone)]
+    pub struct Never;
+
+    impl ChunkerImpl for Never {
+        fn find_boundary(&mut self, _data: &[u8]) -> Option<usize> {
+            None
+        }
+    }
+
+    #[test]
+    fn test_past_4gib() {
+        // The offsets don't fit in a usize on 32-bit targets
+        let data = vec![0u8; 1 << 26];
+        let mut incremental =
+            Chunker::new(Never).max_size(3 << 30).incremental();
+        let mut result = Vec::new();
+        for _ in 0..80 {
+            result.extend(incremental.feed(&data));
+        }
+        assert_eq!(incremental.position(), 5 << 30);
+        result.extend(incremental.finish());
+        assert_eq!(result, vec![3 << 30, 5 << 30]);
+
+        let info = ChunkInfo {
+            start: 3 << 30,
+            length: 2 << 30,
+            kind: BoundaryKind::EndOfStream,
+        };
+        assert_eq!(info.end(), 5 << 30);
+        let info = ChunkInfo {
+            start: u64::max_value(),
+            length: 1,
+            kind: BoundaryKind::EndOfStream,
+        };
+        assert_eq!(info.end(), u64::max_value());
+    }
+
     #[test]
     fn test_slices() {
         let (chunker, data, _, expected) = base();
@@ -685,6 +1694,262 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_boundary_kind() {
+        use super::BoundaryKind::{EndOfStream, MaxSize, Natural};
+
+        let (chunker, _, reader, _) = base();
+        let mut result = Vec::new();
+        for chunk_info in chunker.max_size(5).chunks(reader) {
+            let chunk_info = chunk_info.unwrap();
+            result.push((chunk_info.end(), chunk_info.kind()));
+        }
+        // The chunks ending at 8 and 32 have the maximum size, but they end at
+        // a boundary found in the content
+        assert_eq!(
+            result,
+            vec![
+                (3, Natural), (8, Natural), (12, Natural), (14, Natural),
+                (19, MaxSize), (24, MaxSize), (27, Natural), (32, Natural),
+                (33, EndOfStream),
+            ]
+        );
+
+        // Same thing from the buffered stream
+        let (chunker, _, reader, _) = base();
+        let mut kinds = Vec::new();
+        let mut stream = chunker.max_size(5).stream_bufread(reader);
+        // The stream can't be used while the chunk borrows it, so only keep
+        // whether it is the end
+        let is_end = |chunk: ChunkInput| match chunk {
+            ChunkInput::Data(_) => false,
+            ChunkInput::End => true,
+        };
+        while let Some(end) = stream.read().map(|c| is_end(c.unwrap())) {
+            if end {
+                kinds.push(stream.boundary_kind());
+            }
+        }
+        assert_eq!(kinds, result.iter().map(|r| r.1).collect::<Vec<_>>());
+    }
+
+    /// Reader that fails every other call, and reads at most 3 bytes.
+    pub struct Flaky<R: Read> {
+        inner: R,
+        kind: io::ErrorKind,
+        fail: bool,
+    }
+
+    impl<R: Read> Flaky<R> {
+        pub fn new(inner: R, kind: io::ErrorKind) -> Flaky<R> {
+            Flaky {
+                inner,
+                kind,
+                fail: true,
+            }
+        }
+    }
+
+    impl<R: Read> Read for Flaky<R> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.fail = !self.fail;
+            if !self.fail {
+                return Err(io::Error::new(self.kind, "flaky"));
+            }
+            let len = buf.len().min(3);
+            self.inner.read(&mut buf[..len])
+        }
+    }
+
+    #[test]
+    fn test_interrupted() {
+        let (chunker, _, reader, expected) = base();
+        let reader = Flaky::new(reader, io::ErrorKind::Interrupted);
+        let mut result = chunker.all_chunks(reader).unwrap().join(&b'|');
+        result.push(b'|');
+        assert_eq!(
+            from_utf8(&result).unwrap(),
+            from_utf8(&expected).unwrap()
+        );
+
+        let (chunker, _, reader, expected) = base();
+        let reader = Flaky::new(reader, io::ErrorKind::Interrupted);
+        let mut result = Vec::new();
+        let mut stream = chunker.stream_bufread(BufReader::new(reader));
+        while let Some(chunk) = stream.read() {
+            match chunk.unwrap() {
+                ChunkInput::Data(d) => result.extend(d),
+                ChunkInput::End => result.push(b'|'),
+            }
+        }
+        assert_eq!(
+            from_utf8(&result).unwrap(),
+            from_utf8(&expected).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_resume_after_error() {
+        let (chunker, _, reader, expected) = base();
+        let reader = Flaky::new(reader, io::ErrorKind::Other);
+        let mut result = Vec::new();
+        let mut errors = Vec::new();
+        for chunk in chunker.whole_chunks(reader) {
+            match chunk {
+                Ok(chunk) => {
+                    result.extend(chunk);
+                    result.push(b'|');
+                }
+                Err(e) => {
+                    assert_eq!(e.kind(), io::ErrorKind::Other);
+                    errors.push((e.offset(), e.chunk_index(), e.partial_len()));
+                }
+            }
+        }
+        // One failure before each of the 11 reads of data, the one hitting the
+        // end of the stream, and the one after that
+        assert_eq!(errors.len(), 13);
+        assert_eq!(&errors[..3], &[(0, 0, 0), (3, 1, 0), (6, 1, 3)]);
+        assert_eq!(&errors[11..], &[(33, 6, 7), (33, 7, 0)]);
+        assert_eq!(
+            from_utf8(&result).unwrap(),
+            from_utf8(&expected).unwrap()
+        );
+
+        // Errors from a BufRead carry the same positions
+        let (chunker, _, reader, expected) = base();
+        let reader = Flaky::new(reader, io::ErrorKind::Other);
+        let mut result = Vec::new();
+        let mut errors = Vec::new();
+        let mut stream = chunker.stream_bufread(BufReader::new(reader));
+        while let Some(chunk) = stream.read() {
+            match chunk {
+                Ok(ChunkInput::Data(d)) => result.extend(d),
+                Ok(ChunkInput::End) => result.push(b'|'),
+                Err(e) => {
+                    // The data was already returned
+                    assert!(e.partial().is_none());
+                    errors.push((e.offset(), e.chunk_index(), e.partial_len()))
+                }
+            }
+        }
+        assert_eq!(errors.len(), 13);
+        assert_eq!(&errors[..3], &[(0, 0, 0), (3, 1, 0), (6, 1, 3)]);
+        assert_eq!(&errors[11..], &[(33, 6, 7), (33, 7, 0)]);
+        assert_eq!(
+            from_utf8(&result).unwrap(),
+            from_utf8(&expected).unwrap()
+        );
+
+        let (chunker, _, reader, _) = base();
+        let reader = Flaky::new(reader, io::ErrorKind::Other);
+        let result: Vec<(u64, u64)> = chunker
+            .chunks(reader)
+            .filter_map(|c| c.ok())
+            .map(|c| (c.start(), c.length()))
+            .collect();
+        assert_eq!(
+            result,
+            vec![
+                (0, 3), (3, 5), (8, 4), (12, 2),
+                (14, 6), (20, 6), (26, 7),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_take_partial() {
+        let (chunker, _, reader, _) = base();
+        let reader = Flaky::new(reader, io::ErrorKind::Other);
+        let mut chunks = chunker.whole_chunks(reader);
+        let mut result = Vec::new();
+        let mut taken = 0;
+        while let Some(chunk) = chunks.next() {
+            match chunk {
+                Ok(chunk) => {
+                    result.extend(chunk);
+                    result.push(b'|');
+                    taken = 0;
+                }
+                Err(e) => {
+                    let partial = chunks.take_partial();
+                    assert_eq!(e.partial(), Some(&partial[..]));
+                    taken += partial.len() as u64;
+                    assert_eq!(taken, e.partial_len());
+                    if !partial.is_empty() {
+                        result.extend(partial);
+                        result.push(b'/');
+                    }
+                }
+            }
+        }
+        assert_eq!(
+            from_utf8(&result).unwrap(),
+            "def|ghi/jk|l/mno|pq|r/stu/vw|x/yz1/23|4/567/890/|"
+        );
+    }
+
+    #[test]
+    fn test_chunk_error() {
+        let (chunker, _, reader, _) = base();
+        let reader = Flaky::new(reader, io::ErrorKind::NotFound);
+        let err = chunker.all_chunks(reader).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        assert_eq!(
+            err.to_string(),
+            "flaky (at offset 0, in chunk 0 after 0 bytes)"
+        );
+        let err = err.into_inner().unwrap().downcast::<ChunkError>().unwrap();
+        assert_eq!(err.into_inner().to_string(), "flaky");
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde() {
+        use super::{
+            Algorithm, BoundaryKind, ChunkInfo, ChunkerBuilder, SizeLimited,
+        };
+        use serde_json::{from_str, to_string};
+
+        let info = ChunkInfo {
+            start: 3,
+            length: 5,
+            kind: BoundaryKind::MaxSize,
+        };
+        let json = to_string(&info).unwrap();
+        assert_eq!(json, r#"{"start":3,"length":5,"kind":"max_size"}"#);
+        assert_eq!(from_str::<ChunkInfo>(&json).unwrap(), info);
+
+        // Only the parameters are saved, not the state
+        let (_, data, _, _) = base();
+        let mut inner = SizeLimited::new(ZPAQ::new(3), 5);
+        Chunker::new(&mut inner).slices(&data[..4]).count();
+        let json = to_string(&inner).unwrap();
+        assert_eq!(
+            json,
+            concat!(
+                r#"{"inner":{"threshold":536870912,"salt":0},"#,
+                r#""min_size":0,"max_size":5}"#
+            )
+        );
+        let inner: SizeLimited<ZPAQ> = from_str(&json).unwrap();
+        let result: Vec<&[u8]> = Chunker::new(inner).slices(data).collect();
+        let expected: Vec<&[u8]> =
+            Chunker::new(ZPAQ::new(3)).max_size(5).slices(data).collect();
+        assert_eq!(result, expected);
+
+        let builder = ChunkerBuilder::new(Algorithm::Gear).max_size(65536);
+        let json = to_string(&builder).unwrap();
+        assert_eq!(
+            json,
+            concat!(
+                r#"{"algorithm":"gear","min_size":0,"avg_size":8192,"#,
+                r#""max_size":65536,"seed":0}"#
+            )
+        );
+        assert_eq!(from_str::<ChunkerBuilder>(&json).unwrap(), builder);
+    }
+
     struct RngFile<R: Rng>(R);
 
     impl<R: Rng> Read for RngFile<R> {
@@ -0,0 +1,187 @@
+//! Chunking files through memory-mapping.
+
+use memmap2::Mmap;
+use std::fs::File;
+use std::io;
+use std::path::Path;
+
+use super::{ChunkError, Chunker, ChunkerImpl, WholeChunks};
+
+impl<I: ChunkerImpl> Chunker<I> {
+    /// Iterate on chunks of a file as slices, memory-mapping it.
+    ///
+    /// This gives you the same slices as if you used `slices()` on the whole
+    /// content of the file, without reading it all into memory first. Files
+    /// that can't be mapped (pipes, devices, files too large for the address
+    /// space, or any file the system refuses to map) are read with
+    /// `whole_chunks()` instead.
+    ///
+    /// Note that if the file is modified by another process while it is
+    /// mapped, the data you get is undefined, and truncating it might crash
+    /// your program.
+    ///
+    /// Because the slices borrow from the mapping, you have to use a while
+    /// loop:
+    ///
+    /// ```
+    /// # use cdchunking::{Chunker, ZPAQ};
+    /// # let chunker = Chunker::new(ZPAQ::new(13));
+    /// # let path = file!();
+    /// let mut slices = chunker.slices_from_file(path).unwrap();
+    /// while let Some(slice) = slices.read() {
+    ///     let slice = slice.expect("Error reading from file");
+    ///     println!("{:?}", slice);
+    /// }
+    /// ```
+    pub fn slices_from_file<P: AsRef<Path>>(
+        self,
+        path: P,
+    ) -> io::Result<FileSlices<I>> {
+        let file = File::open(path)?;
+        let metadata = file.metadata()?;
+        let mapped = if metadata.is_file()
+            && metadata.len() > 0
+            && metadata.len() <= usize::max_value() as u64
+        {
+            // Mapping can still fail, e.g. for lack of address space
+            map(&file).ok()
+        } else {
+            None
+        };
+        let source = match mapped {
+            Some(map) => FileSource::Mapped {
+                map,
+                inner: self.inner,
+                pos: 0,
+            },
+            None => FileSource::Stream {
+                chunks: Box::new(self.whole_chunks(file)),
+                current: Vec::new(),
+            },
+        };
+        Ok(FileSlices { source })
+    }
+}
+
+#[allow(unsafe_code)]
+fn map(file: &File) -> io::Result<Mmap> {
+    // This is only unsafe if the file gets modified while we hold the map,
+    // which is documented in slices_from_file()
+    unsafe { Mmap::map(file) }
+}
+
+enum FileSource<I: ChunkerImpl> {
+    Mapped {
+        map: Mmap,
+        inner: I,
+        pos: usize,
+    },
+    Stream {
+        chunks: Box<WholeChunks<File, I>>,
+        current: Vec<u8>,
+    },
+}
+
+pub struct FileSlices<I: ChunkerImpl> {
+    source: FileSource<I>,
+}
+
+impl<I: ChunkerImpl> FileSlices<I> {
+    /// Returns the next chunk.
+    ///
+    /// If the file is mapped, this is a slice of the mapping. Otherwise, this
+    /// is a slice of an internal buffer holding the chunk.
+    // Can't be Iterator because of the borrow
+    pub fn read(&mut self) -> Option<Result<&[u8], ChunkError>> {
+        match self.source {
+            FileSource::Mapped {
+                ref map,
+                ref mut inner,
+                ref mut pos,
+            } => {
+                if *pos == map.len() {
+                    return None;
+                }
+                let start = *pos;
+                if let Some(split) = inner.find_boundary(&map[start..]) {
+                    assert!(start + split < map.len());
+                    *pos += split + 1;
+                    inner.reset();
+                } else {
+                    *pos = map.len();
+                }
+                Some(Ok(&map[start..*pos]))
+            }
+            FileSource::Stream {
+                ref mut chunks,
+                ref mut current,
+            } => match chunks.next() {
+                None => None,
+                // Empty input gives an empty chunk, unlike slices()
+                Some(Ok(ref chunk)) if chunk.is_empty() => None,
+                Some(Err(e)) => Some(Err(e)),
+                Some(Ok(chunk)) => {
+                    *current = chunk;
+                    Some(Ok(&current[..]))
+                }
+            },
+        }
+    }
+
+    /// Whether the file was memory-mapped.
+    pub fn is_mapped(&self) -> bool {
+        match self.source {
+            FileSource::Mapped { .. } => true,
+            FileSource::Stream { .. } => false,
+        }
+    }
+
+    /// The whole content of the file, if it was memory-mapped.
+    pub fn mapped_data(&self) -> Option<&[u8]> {
+        match self.source {
+            FileSource::Mapped { ref map, .. } => Some(&map[..]),
+            FileSource::Stream { .. } => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{self, File};
+    use std::io::Write;
+
+    use super::super::{Chunker, ZPAQ};
+
+    #[test]
+    fn test_slices_from_file() {
+        let data = b"defghijklmnopqrstuvwxyz1234567890";
+        let path = ::std::env::temp_dir()
+            .join(format!("cdchunking-test-mmap-{}", ::std::process::id()));
+        File::create(&path).unwrap().write_all(data).unwrap();
+
+        let mut result = Vec::new();
+        let mut slices =
+            Chunker::new(ZPAQ::new(3)).slices_from_file(&path).unwrap();
+        assert!(slices.is_mapped());
+        while let Some(slice) = slices.read() {
+            result.extend(slice.unwrap());
+            result.push(b'|');
+        }
+        drop(slices);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(
+            &result[..],
+            &b"def|ghijk|lmno|pq|rstuvw|xyz123|4567890|"[..]
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_slices_from_device() {
+        let mut slices = Chunker::new(ZPAQ::new(3))
+            .slices_from_file("/dev/null")
+            .unwrap();
+        assert!(!slices.is_mapped());
+        assert!(slices.read().is_none());
+    }
+}
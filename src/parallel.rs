@@ -0,0 +1,365 @@
+//! Parallel chunking of streams and in-memory buffers, using rayon.
+//!
+//! The input is split into segments, and boundaries are searched for in each
+//! segment on a different thread, starting from the beginning of the segment.
+//! Those boundaries are speculative: the chunk boundaries of the data before
+//! the segment might not line up with the start of the segment. However, once
+//! a boundary of the sequential chunking matches a speculative boundary, the
+//! rest of the speculative boundaries are correct, since the `ChunkerImpl` is
+//! reset there. The results are merged by chunking sequentially from the end
+//! of each segment until the boundaries synchronize again.
+
+use rayon::prelude::*;
+use std::io::{self, Read};
+
+use super::{BoundaryKind, ChunkError, ChunkInfo, Chunker, ChunkerImpl};
+
+#[cfg(not(test))]
+const SEGMENT_SIZE: usize = 1 << 20;
+#[cfg(test)]
+const SEGMENT_SIZE: usize = 16;
+
+/// Finds the boundaries in `data[start..end]`, starting fresh at `start`.
+fn scan<I: ChunkerImpl + Clone>(
+    inner: &I,
+    data: &[u8],
+    start: usize,
+    end: usize,
+) -> Vec<(usize, BoundaryKind)> {
+    let mut inner = inner.clone();
+    let mut boundaries = Vec::new();
+    let mut pos = start;
+    while let Some((split, kind)) = inner.find_boundary_kind(&data[pos..end]) {
+        pos += split + 1;
+        boundaries.push((pos, kind));
+        inner.reset();
+    }
+    boundaries
+}
+
+/// Finds all the boundaries in `data`, using multiple threads.
+///
+/// `carry` is the state of the chunker if `data` starts in the middle of a
+/// chunk. This returns the offset of the end of each chunk and why it ended,
+/// not including the end of the data unless there is a boundary there, and
+/// the state of the chunker at the end of the data if it is in the middle of
+/// a chunk.
+pub(crate) fn find_boundaries<I>(
+    inner: &I,
+    mut carry: Option<I>,
+    data: &[u8],
+    segment_size: usize,
+) -> (Vec<(usize, BoundaryKind)>, Option<I>)
+where
+    I: ChunkerImpl + Clone + Sync,
+{
+    // Find speculative boundaries in each segment
+    let segments: Vec<Vec<(usize, BoundaryKind)>> =
+        (0..(data.len() + segment_size - 1) / segment_size)
+            .into_par_iter()
+            .map(|k| {
+                let start = k * segment_size;
+                let end = (start + segment_size).min(data.len());
+                scan(inner, data, start, end)
+            })
+            .collect();
+
+    // Merge them
+    let mut boundaries = Vec::new();
+    let mut pos = 0;
+    while pos < data.len() {
+        if carry.is_none() {
+            let k = pos / segment_size;
+            let segment = &segments[k];
+            let synchronized = if pos == k * segment_size {
+                &segment[..]
+            } else {
+                match segment.binary_search_by_key(&pos, |&(p, _)| p) {
+                    Ok(i) => &segment[i + 1..],
+                    Err(_) => &[],
+                }
+            };
+            if let Some(&(last, _)) = synchronized.last() {
+                boundaries.extend_from_slice(synchronized);
+                pos = last;
+                continue;
+            }
+        }
+
+        // Not synchronized, move on to the next boundary sequentially
+        let mut state = carry.take().unwrap_or_else(|| inner.clone());
+        match state.find_boundary_kind(&data[pos..]) {
+            Some((split, kind)) => {
+                pos += split + 1;
+                boundaries.push((pos, kind));
+            }
+            None => {
+                carry = Some(state);
+                break;
+            }
+        }
+    }
+    (boundaries, carry)
+}
+
+impl<I: ChunkerImpl + Clone + Send + Sync> Chunker<I> {
+    /// Describes the chunks, finding boundaries using multiple threads.
+    ///
+    /// This works like `chunks()`, and returns the exact same chunks. Large
+    /// blocks of data are read at once, and the boundaries in each block are
+    /// found in parallel, using the rayon thread pool.
+    ///
+    /// The reader doesn't need to implement `Seek`: the blocks are read
+    /// sequentially, only the search for boundaries is parallel. If the data
+    /// is in memory already, for example a memory-mapped file, use
+    /// `par_slices()` to avoid copying it.
+    ///
+    /// Read errors are returned as a `ChunkError`, and you can keep iterating
+    /// afterwards. Since whole blocks are read ahead, its `partial_len()`
+    /// can include data past boundaries that were not returned yet.
+    ///
+    /// The `ChunkerImpl` gets cloned for each segment of the data; it should
+    /// not be used before calling this.
+    pub fn par_chunks<R: Read>(self, reader: R) -> ParChunks<R, I> {
+        ParChunks {
+            reader,
+            inner: self.inner,
+            carry: None,
+            block_size: SEGMENT_SIZE * rayon::current_num_threads(),
+            buffer: Vec::new(),
+            offset: 0,
+            boundaries: Vec::new(),
+            next_boundary: 0,
+            chunk_start: 0,
+            chunk_index: 0,
+            eof: false,
+            done: false,
+        }
+    }
+
+    /// Splits an in-memory buffer in chunks, finding boundaries using
+    /// multiple threads.
+    ///
+    /// This returns the exact same slices as `slices()`. This is useful for
+    /// very large buffers, for example memory-mapped files.
+    ///
+    /// The `ChunkerImpl` gets cloned for each segment of the data; it should
+    /// not be used before calling this.
+    pub fn par_slices(self, buffer: &[u8]) -> Vec<&[u8]> {
+        let (boundaries, _) =
+            find_boundaries(&self.inner, None, buffer, SEGMENT_SIZE);
+        let mut slices = Vec::with_capacity(boundaries.len() + 1);
+        let mut start = 0;
+        for (end, _) in boundaries {
+            slices.push(&buffer[start..end]);
+            start = end;
+        }
+        if start < buffer.len() {
+            slices.push(&buffer[start..]);
+        }
+        slices
+    }
+}
+
+/// Iterator returned by `Chunker::par_chunks()`.
+///
+/// It yields the same `ChunkInfo`s as `ChunkInfoStream`, reading the input a
+/// block at a time and finding the boundaries in each block in parallel.
+pub struct ParChunks<R: Read, I: ChunkerImpl + Clone + Send + Sync> {
+    reader: R,
+    inner: I,
+    carry: Option<I>, // State of the chunker in the middle of a chunk
+    block_size: usize,
+    buffer: Vec<u8>,
+    offset: u64, // Position of the buffer in the stream
+    boundaries: Vec<(u64, BoundaryKind)>,
+    next_boundary: usize, // Index of the next boundary to return
+    chunk_start: u64,     // Start of the current chunk in the stream
+    chunk_index: u64,     // Index of the current chunk
+    eof: bool,
+    done: bool,
+}
+
+impl<R: Read, I: ChunkerImpl + Clone + Send + Sync> ParChunks<R, I> {
+    /// Reads the next block and finds the boundaries in it.
+    ///
+    /// If reading fails, the data read so far is kept, and reading continues
+    /// from there on the next call.
+    fn fill(&mut self) -> io::Result<()> {
+        let target = self.block_size;
+        let mut len = self.buffer.len();
+        self.buffer.resize(target, 0);
+        while len < target {
+            match self.reader.read(&mut self.buffer[len..]) {
+                Ok(0) => {
+                    self.eof = true;
+                    break;
+                }
+                Ok(l) => len += l,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => {
+                    self.buffer.truncate(len);
+                    return Err(e);
+                }
+            }
+        }
+        self.buffer.truncate(len);
+
+        // Only the new data gets scanned, the chunker carries on from where
+        // the previous block ended
+        let (boundaries, carry) = find_boundaries(
+            &self.inner,
+            self.carry.take(),
+            &self.buffer,
+            SEGMENT_SIZE,
+        );
+        let offset = self.offset;
+        self.boundaries = boundaries
+            .into_iter()
+            .map(|(end, kind)| (offset + end as u64, kind))
+            .collect();
+        self.next_boundary = 0;
+        self.carry = carry;
+        self.offset += len as u64;
+        self.buffer.clear();
+        Ok(())
+    }
+}
+
+impl<R: Read, I: ChunkerImpl + Clone + Send + Sync> Iterator
+    for ParChunks<R, I>
+{
+    type Item = Result<ChunkInfo, ChunkError>;
+
+    fn next(&mut self) -> Option<Result<ChunkInfo, ChunkError>> {
+        loop {
+            if self.next_boundary < self.boundaries.len() {
+                let (end, kind) = self.boundaries[self.next_boundary];
+                self.next_boundary += 1;
+                let start = self.chunk_start;
+                self.chunk_start = end;
+                self.chunk_index += 1;
+                return Some(Ok(ChunkInfo {
+                    start,
+                    length: end - start,
+                    kind,
+                }));
+            }
+            if self.eof {
+                if self.done {
+                    return None;
+                }
+                self.done = true;
+                // Like with chunks(), an empty stream is a single empty chunk
+                if self.chunk_start < self.offset || self.offset == 0 {
+                    return Some(Ok(ChunkInfo {
+                        start: self.chunk_start,
+                        length: self.offset - self.chunk_start,
+                        kind: BoundaryKind::EndOfStream,
+                    }));
+                }
+                return None;
+            }
+            if let Err(e) = self.fill() {
+                let offset = self.offset + self.buffer.len() as u64;
+                return Some(Err(ChunkError::new(
+                    e,
+                    offset,
+                    self.chunk_index,
+                    offset - self.chunk_start,
+                )));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use super::super::testing::{check_chunkers, random_data, Check};
+    use super::super::tests::{base, Flaky, Never};
+    use super::super::{Chunker, ChunkerImpl};
+
+    /// Checks that par_chunks() and par_slices() match chunks() and slices().
+    struct SameChunks<'a>(&'a [u8]);
+
+    impl<'a> Check for SameChunks<'a> {
+        fn check<I, F>(&mut self, chunker: F)
+        where
+            I: ChunkerImpl + Clone + Send + Sync,
+            F: Fn() -> Chunker<I>,
+        {
+            let data = self.0;
+            let expected: Vec<_> = chunker()
+                .chunks(data)
+                .map(|c| c.unwrap())
+                .map(|c| (c.start(), c.length(), c.kind()))
+                .collect();
+            let result: Vec<_> = chunker()
+                .par_chunks(data)
+                .map(|c| c.unwrap())
+                .map(|c| (c.start(), c.length(), c.kind()))
+                .collect();
+            assert_eq!(result, expected);
+
+            let expected: Vec<&[u8]> = chunker().slices(data).collect();
+            let result = chunker().par_slices(data);
+            assert_eq!(result.len(), expected.len());
+            for (r, e) in result.iter().zip(expected.iter()) {
+                assert_eq!(r.as_ptr(), e.as_ptr());
+                assert_eq!(r.len(), e.len());
+            }
+        }
+    }
+
+    #[test]
+    fn test_par_chunks() {
+        let mut data = random_data(10000, 0);
+        check_chunkers(&mut SameChunks(&data), 3);
+        check_chunkers(&mut SameChunks(&data), 6);
+
+        // Chunks that span multiple segments
+        for (i, b) in data.iter_mut().enumerate() {
+            *b = (i % 7) as u8;
+        }
+        check_chunkers(&mut SameChunks(&data), 3);
+
+        // Chunks that span multiple blocks
+        SameChunks(&data).check(|| Chunker::new(Never));
+        SameChunks(&data).check(|| Chunker::new(Never).max_size(1000));
+    }
+
+    #[test]
+    fn test_par_chunks_errors() {
+        let (chunker, _, reader, _) = base();
+        let expected: Vec<_> =
+            chunker.chunks(reader).map(|c| c.unwrap()).collect();
+
+        let (chunker, _, reader, _) = base();
+        let reader = Flaky::new(reader, io::ErrorKind::Other);
+        let mut result = Vec::new();
+        let mut errors = Vec::new();
+        for chunk in chunker.par_chunks(reader) {
+            match chunk {
+                Ok(chunk) => result.push(chunk),
+                Err(e) => {
+                    errors.push((e.offset(), e.chunk_index(), e.partial_len()))
+                }
+            }
+        }
+        assert_eq!(result, expected);
+        // How often the reader gets called depends on the number of threads,
+        // but the errors count from the start of a chunk
+        assert_eq!(errors[0], (0, 0, 0));
+        for (offset, chunk_index, partial_len) in errors {
+            let chunk = &expected[chunk_index as usize];
+            assert_eq!(offset - partial_len, chunk.start());
+        }
+    }
+
+    #[test]
+    fn test_par_chunks_empty() {
+        check_chunkers(&mut SameChunks(b""), 3);
+    }
+}
@@ -0,0 +1,273 @@
+//! Evaluating how well chunk boundaries resist modifications of the data.
+//!
+//! The point of content-defined chunking is that a local change to the data
+//! only changes the chunks around it. This chunks two versions of some data,
+//! and counts how many chunks of the new version already existed in the old
+//! one.
+
+use std::collections::HashSet;
+use std::fmt;
+
+use super::xorshift::XorShift;
+use super::{Chunker, ChunkerImpl};
+
+/// Applies random insertions, deletions and byte flips to `data`.
+fn mutate(data: &[u8], edits: usize, seed: u64) -> Vec<u8> {
+    let mut rng = XorShift::new(seed);
+    let mut data = data.to_vec();
+    for _ in 0..edits {
+        let pos = rng.below(data.len() + 1);
+        match rng.below(3) {
+            0 => {
+                let len = 1 + rng.below(16);
+                let bytes: Vec<u8> =
+                    (0..len).map(|_| rng.next() as u8).collect();
+                data.splice(pos..pos, bytes);
+            }
+            1 if pos < data.len() => {
+                let len = (1 + rng.below(16)).min(data.len() - pos);
+                data.drain(pos..pos + len);
+            }
+            _ if pos < data.len() => data[pos] ^= 1 << rng.below(8),
+            _ => data.push(rng.next() as u8),
+        }
+    }
+    data
+}
+
+/// Result of comparing the chunks of two versions of some data.
+///
+/// A chunk of the modified data is "reused" if a chunk with the exact same
+/// content exists in the original data; those would be de-duplicated. The
+/// other ones are "new", and consecutive new chunks form a damaged region.
+#[derive(Clone, Debug)]
+pub struct ResilienceReport {
+    original_chunks: usize,
+    chunks: usize,
+    reused_chunks: usize,
+    new_bytes: usize,
+    damaged_regions: usize,
+    max_spread: usize,
+}
+
+impl ResilienceReport {
+    /// The number of chunks in the original data.
+    pub fn original_chunks(&self) -> usize {
+        self.original_chunks
+    }
+
+    /// The number of chunks in the modified data.
+    pub fn chunks(&self) -> usize {
+        self.chunks
+    }
+
+    /// The number of chunks of the modified data that are in the original.
+    pub fn reused_chunks(&self) -> usize {
+        self.reused_chunks
+    }
+
+    /// The number of chunks of the modified data that are not in the
+    /// original.
+    pub fn new_chunks(&self) -> usize {
+        self.chunks - self.reused_chunks
+    }
+
+    /// The fraction of the chunks of the modified data that are in the
+    /// original, or 1 if there are no chunks.
+    pub fn reused_fraction(&self) -> f64 {
+        if self.chunks == 0 {
+            1.0
+        } else {
+            self.reused_chunks as f64 / self.chunks as f64
+        }
+    }
+
+    /// The total size of the new chunks, i.e. how much data would have to be
+    /// stored again.
+    pub fn new_bytes(&self) -> usize {
+        self.new_bytes
+    }
+
+    /// The number of runs of consecutive new chunks.
+    pub fn damaged_regions(&self) -> usize {
+        self.damaged_regions
+    }
+
+    /// The size of the largest run of consecutive new chunks, i.e. how far
+    /// the damage of an edit spread.
+    pub fn max_spread(&self) -> usize {
+        self.max_spread
+    }
+}
+
+impl fmt::Display for ResilienceReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} of {} chunks reused ({:.1}%), {} new bytes in {} regions, \
+             largest {} bytes",
+            self.reused_chunks,
+            self.chunks,
+            self.reused_fraction() * 100.0,
+            self.new_bytes,
+            self.damaged_regions,
+            self.max_spread
+        )
+    }
+}
+
+impl<I: ChunkerImpl + Clone> Chunker<I> {
+    /// Compares the chunks of two versions of some data.
+    ///
+    /// This is useful to check how content-defined the chunking is: after a
+    /// local change, only a few chunks around it should be new.
+    pub fn compare(self, original: &[u8], modified: &[u8]) -> ResilienceReport {
+        self.compare_corpus(&[original], &[modified])
+    }
+
+    /// Compares the chunks of two versions of each file in a corpus.
+    ///
+    /// Chunks of the modified files are reused if they appear in any of the
+    /// original files, but damaged regions don't span files.
+    fn compare_corpus<A, B>(
+        self,
+        originals: &[A],
+        modified: &[B],
+    ) -> ResilienceReport
+    where
+        A: AsRef<[u8]>,
+        B: AsRef<[u8]>,
+    {
+        let mut known = HashSet::new();
+        let mut original_chunks = 0;
+        for original in originals {
+            let chunker = Chunker::new(self.inner.clone());
+            for slice in chunker.slices(original.as_ref()) {
+                known.insert(slice);
+                original_chunks += 1;
+            }
+        }
+        let mut report = ResilienceReport {
+            original_chunks,
+            chunks: 0,
+            reused_chunks: 0,
+            new_bytes: 0,
+            damaged_regions: 0,
+            max_spread: 0,
+        };
+        for data in modified {
+            let mut spread = 0;
+            let chunker = Chunker::new(self.inner.clone());
+            for slice in chunker.slices(data.as_ref()) {
+                report.chunks += 1;
+                if known.contains(slice) {
+                    report.reused_chunks += 1;
+                    spread = 0;
+                } else {
+                    if spread == 0 {
+                        report.damaged_regions += 1;
+                    }
+                    spread += slice.len();
+                    report.new_bytes += slice.len();
+                    report.max_spread = report.max_spread.max(spread);
+                }
+            }
+        }
+        report
+    }
+
+    /// Applies random edits to each file of a corpus, and compares the
+    /// chunks of both versions.
+    ///
+    /// Each file gets `edits` edits, each of which is either the insertion
+    /// of 1 to 16 random bytes, the deletion of 1 to 16 bytes, or the flip
+    /// of a single bit, at a random position. The same seed always gives the
+    /// same edits.
+    ///
+    /// ```
+    /// # use cdchunking::{Chunker, ZPAQ};
+    /// let corpus: Vec<Vec<u8>> = (0..4u32)
+    ///     .map(|file| {
+    ///         (0..25_000u32)
+    ///             .map(|i| (i ^ file).wrapping_mul(2_654_435_761) >> 24)
+    ///             .map(|b| b as u8)
+    ///             .collect()
+    ///     })
+    ///     .collect();
+    /// let report = Chunker::new(ZPAQ::new(10)).resilience(&corpus, 5, 42);
+    /// println!("{}", report);
+    /// ```
+    pub fn resilience<D: AsRef<[u8]>>(
+        self,
+        corpus: &[D],
+        edits: usize,
+        seed: u64,
+    ) -> ResilienceReport {
+        let modified: Vec<Vec<u8>> = corpus
+            .iter()
+            .enumerate()
+            .map(|(i, data)| {
+                mutate(data.as_ref(), edits, seed.wrapping_add(i as u64))
+            })
+            .collect();
+        self.compare_corpus(corpus, &modified)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::testing::{check_chunkers, random_data, Check};
+    use super::super::{Chunker, ChunkerImpl};
+    use super::super::xorshift::XorShift;
+    use super::mutate;
+
+    struct Resilience(Vec<Vec<u8>>);
+
+    impl Check for Resilience {
+        fn check<I, F>(&mut self, chunker: F)
+        where
+            I: ChunkerImpl + Clone + Send + Sync,
+            F: Fn() -> Chunker<I>,
+        {
+            let corpus = &self.0;
+            let size: usize = corpus.iter().map(|d| d.len()).sum();
+
+            // Identical data
+            let report = chunker().compare(&corpus[0], &corpus[0]);
+            assert_eq!(report.new_chunks(), 0);
+            assert_eq!(report.chunks(), report.original_chunks());
+
+            // A single edit per file should only affect the chunks around it
+            for seed in 0..10 {
+                let report = chunker().resilience(corpus, 1, seed);
+                assert!(report.damaged_regions() <= corpus.len());
+                assert!(report.new_chunks() <= 3 * corpus.len());
+                assert!(report.reused_fraction() > 0.9);
+            }
+
+            // Spread-out edits damage separate regions
+            let report = chunker().resilience(corpus, 8, 1);
+            assert!(report.damaged_regions() <= 8 * corpus.len());
+            assert!(report.new_bytes() < size / 4);
+        }
+    }
+
+    #[test]
+    fn test_resilience() {
+        let corpus = (1..4).map(|seed| random_data(1 << 15, seed)).collect();
+        check_chunkers(&mut Resilience(corpus), 8);
+    }
+
+    #[test]
+    fn test_mutate() {
+        let data = random_data(1000, 1);
+        assert_eq!(mutate(&data, 0, 3), data);
+        assert_eq!(mutate(&data, 5, 3), mutate(&data, 5, 3));
+        assert!(mutate(&data, 5, 3) != mutate(&data, 5, 4));
+        assert!(!mutate(b"", 3, 0).is_empty());
+
+        // The seed that would give the zero state
+        let mut rng = XorShift::new(0x2545_f491_4f6c_dd1d);
+        assert!(rng.next() != 0 && rng.next() != 0);
+    }
+}
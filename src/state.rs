@@ -0,0 +1,488 @@
+//! Saving and restoring the state of chunking, to resume it later.
+
+use std::io::{self, Read};
+use std::num::Wrapping;
+
+use super::{
+    AnyChunker, ChunkInfoStream, Chunker, ChunkerImpl, Emitter, Incremental,
+    SizeLimited, ZPAQ,
+};
+
+/// A `ChunkerImpl` whose internal state can be saved and restored.
+///
+/// The parameters are saved along with the state that changes while chunking.
+/// Restoring the state into an object created with different parameters
+/// returns an error, rather than silently finding different boundaries.
+pub trait SaveState: ChunkerImpl {
+    /// Appends the parameters and the internal state to `buf`.
+    fn save_state(&self, buf: &mut Vec<u8>);
+
+    /// Restores the internal state from `buf`, returning how many bytes were
+    /// used.
+    fn load_state(&mut self, buf: &[u8]) -> io::Result<usize>;
+}
+
+fn invalid_state() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "Invalid chunker state")
+}
+
+pub(crate) fn write_u64(buf: &mut Vec<u8>, value: u64) {
+    for i in 0..8 {
+        buf.push((value >> (8 * i)) as u8);
+    }
+}
+
+pub(crate) fn read_u64(buf: &[u8]) -> io::Result<u64> {
+    if buf.len() < 8 {
+        return Err(invalid_state());
+    }
+    let mut value = 0;
+    for (i, &byte) in buf[..8].iter().enumerate() {
+        value |= (byte as u64) << (8 * i);
+    }
+    Ok(value)
+}
+
+/// Appends the parameters of a chunker to `buf`.
+pub(crate) fn write_parameters(buf: &mut Vec<u8>, parameters: &[u64]) {
+    for &value in parameters {
+        write_u64(buf, value);
+    }
+}
+
+/// Checks that the parameters saved in `buf` are the same as `parameters`,
+/// returning how many bytes were used.
+pub(crate) fn check_parameters(
+    buf: &[u8],
+    parameters: &[u64],
+) -> io::Result<usize> {
+    for (i, &value) in parameters.iter().enumerate() {
+        if read_u64(buf.get(8 * i..).unwrap_or(&[]))? != value {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Chunker parameters don't match the saved state",
+            ));
+        }
+    }
+    Ok(8 * parameters.len())
+}
+
+impl SaveState for ZPAQ {
+    fn save_state(&self, buf: &mut Vec<u8>) {
+        write_parameters(buf, &[self.threshold as u64, self.salt as u64]);
+        buf.push(self.c1);
+        buf.extend_from_slice(&self.o1);
+        for i in 0..4 {
+            buf.push((self.h.0 >> (8 * i)) as u8);
+        }
+    }
+
+    fn load_state(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let parameters = [self.threshold as u64, self.salt as u64];
+        let start = check_parameters(buf, &parameters)?;
+        let buf = &buf[start..];
+        if buf.len() < 261 {
+            return Err(invalid_state());
+        }
+        self.c1 = buf[0];
+        self.o1.copy_from_slice(&buf[1..257]);
+        let mut h = 0;
+        for (i, &byte) in buf[257..261].iter().enumerate() {
+            h |= (byte as u32) << (8 * i);
+        }
+        self.h = Wrapping(h);
+        Ok(start + 261)
+    }
+}
+
+impl<I: SaveState> SaveState for SizeLimited<I> {
+    fn save_state(&self, buf: &mut Vec<u8>) {
+        write_parameters(buf, &[self.min_size as u64, self.max_size as u64]);
+        write_u64(buf, self.pos as u64);
+        self.inner.save_state(buf);
+    }
+
+    fn load_state(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let parameters = [self.min_size as u64, self.max_size as u64];
+        let start = check_parameters(buf, &parameters)?;
+        let pos = read_u64(&buf[start..])?;
+        if pos >= self.max_size as u64 {
+            return Err(invalid_state());
+        }
+        self.pos = pos as usize;
+        let start = start + 8;
+        Ok(start + self.inner.load_state(&buf[start..])?)
+    }
+}
+
+impl SaveState for AnyChunker {
+    fn save_state(&self, buf: &mut Vec<u8>) {
+        match *self {
+            AnyChunker::ZPAQ(ref c) => {
+                write_parameters(buf, &[0]);
+                c.save_state(buf);
+            }
+            AnyChunker::Gear(ref c) => {
+                write_parameters(buf, &[1]);
+                c.save_state(buf);
+            }
+        }
+    }
+
+    fn load_state(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match *self {
+            AnyChunker::ZPAQ(ref mut c) => {
+                let start = check_parameters(buf, &[0])?;
+                Ok(start + c.load_state(&buf[start..])?)
+            }
+            AnyChunker::Gear(ref mut c) => {
+                let start = check_parameters(buf, &[1])?;
+                Ok(start + c.load_state(&buf[start..])?)
+            }
+        }
+    }
+}
+
+const STATE_VERSION: u8 = 2;
+
+/// Snapshot of the chunking of a stream, allowing to resume it later.
+///
+/// This records the position in the stream, the index of the current chunk
+/// and the internal state of the `ChunkerImpl`. Restoring it into a `Chunker`
+/// created with the same parameters and feeding it the rest of the stream
+/// will find the exact same boundaries as if chunking had not been
+/// interrupted. Restoring it into one created with different parameters
+/// returns an error.
+///
+/// ```
+/// # use cdchunking::{Chunker, ChunkerState, ZPAQ};
+/// let data = b"abcdefghijklmnopqrstuvwxyz1234567890";
+///
+/// let mut incremental = Chunker::new(ZPAQ::new(3)).incremental();
+/// let mut boundaries: Vec<u64> = incremental.feed(&data[..20]).collect();
+/// let saved = incremental.state().to_bytes();
+///
+/// // Later, maybe in another process
+/// let state = ChunkerState::from_bytes(&saved).unwrap();
+/// let mut incremental = Chunker::new(ZPAQ::new(3))
+///     .resume_incremental(&state)
+///     .unwrap();
+/// boundaries.extend(incremental.feed(&data[20..]));
+/// boundaries.extend(incremental.finish());
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ChunkerState {
+    position: u64,
+    last_boundary: u64,
+    chunk_index: u64,
+    inner: Vec<u8>,
+}
+
+impl ChunkerState {
+    fn new<I: SaveState>(
+        inner: &I,
+        position: u64,
+        last_boundary: u64,
+        chunk_index: u64,
+    ) -> ChunkerState {
+        let mut state = Vec::new();
+        inner.save_state(&mut state);
+        ChunkerState {
+            position,
+            last_boundary,
+            chunk_index,
+            inner: state,
+        }
+    }
+
+    fn restore<I: SaveState>(&self, inner: &mut I) -> io::Result<()> {
+        if inner.load_state(&self.inner)? != self.inner.len() {
+            return Err(invalid_state());
+        }
+        Ok(())
+    }
+
+    /// The position in the stream, from where the data should be fed after
+    /// resuming.
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    /// The index of the chunk that `position()` is in, counting from 0.
+    pub fn chunk_index(&self) -> u64 {
+        self.chunk_index
+    }
+
+    /// Serializes the state to bytes.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(25 + self.inner.len());
+        buf.push(STATE_VERSION);
+        write_u64(&mut buf, self.position);
+        write_u64(&mut buf, self.last_boundary);
+        write_u64(&mut buf, self.chunk_index);
+        buf.extend_from_slice(&self.inner);
+        buf
+    }
+
+    /// Reads back the state serialized by `to_bytes()`.
+    pub fn from_bytes(buf: &[u8]) -> io::Result<ChunkerState> {
+        if buf.len() < 25 || buf[0] != STATE_VERSION {
+            return Err(invalid_state());
+        }
+        let position = read_u64(&buf[1..])?;
+        let last_boundary = read_u64(&buf[9..])?;
+        let chunk_index = read_u64(&buf[17..])?;
+        if last_boundary > position {
+            return Err(invalid_state());
+        }
+        Ok(ChunkerState {
+            position,
+            last_boundary,
+            chunk_index,
+            inner: buf[25..].to_vec(),
+        })
+    }
+}
+
+impl<I: SaveState> Chunker<I> {
+    /// Resumes incremental chunking from a saved state.
+    ///
+    /// The data fed to the returned `Incremental` should start at
+    /// `state.position()` in the stream.
+    pub fn resume_incremental(
+        mut self,
+        state: &ChunkerState,
+    ) -> io::Result<Incremental<I>> {
+        state.restore(&mut self.inner)?;
+        let mut emitter = Emitter::new(self.inner);
+        emitter.resume(
+            state.position,
+            state.last_boundary,
+            state.chunk_index,
+        );
+        Ok(Incremental { emitter })
+    }
+
+    /// Resumes describing the chunks from a saved state.
+    ///
+    /// The reader should be positioned at `state.position()` in the stream.
+    pub fn resume_chunks<R: Read>(
+        mut self,
+        reader: R,
+        state: &ChunkerState,
+    ) -> io::Result<ChunkInfoStream<R, I>> {
+        state.restore(&mut self.inner)?;
+        let mut chunks = self.chunks(reader);
+        chunks.pos = state.position;
+        chunks.last_chunk = state.last_boundary;
+        chunks.stream.emitter.resume(
+            state.position,
+            state.last_boundary,
+            state.chunk_index,
+        );
+        Ok(chunks)
+    }
+}
+
+impl<I: SaveState> Incremental<I> {
+    /// Saves the state of chunking, to resume it later.
+    pub fn state(&self) -> ChunkerState {
+        let emitter = &self.emitter;
+        ChunkerState::new(
+            &emitter.inner,
+            emitter.offset,
+            emitter.chunk_start,
+            emitter.chunk_index,
+        )
+    }
+}
+
+impl<R: Read, I: SaveState> ChunkInfoStream<R, I> {
+    /// Saves the state of chunking, to resume it later.
+    ///
+    /// The state is at the end of what was returned so far: the end of the
+    /// last chunk, or after a `ChunkError`, its `offset()` in the middle of
+    /// the current chunk. More data might have been read from the reader
+    /// already, so resume reading from `state.position()`.
+    pub fn state(&self) -> ChunkerState {
+        let emitter = &self.stream.emitter;
+        ChunkerState::new(
+            &emitter.inner,
+            self.pos,
+            self.last_chunk,
+            emitter.chunk_index,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use super::super::tests::{Flaky, Never};
+    use super::super::{Chunker, ChunkerBuilder, ZPAQ};
+    use super::{ChunkerState, SaveState};
+
+    impl SaveState for Never {
+        fn save_state(&self, _buf: &mut Vec<u8>) {}
+
+        fn load_state(&mut self, _buf: &[u8]) -> io::Result<usize> {
+            Ok(0)
+        }
+    }
+
+    const DATA: &[u8] = b"defghijklmnopqrstuvwxyz1234567890";
+
+    #[test]
+    fn test_resume_incremental() {
+        let mut result = Vec::new();
+
+        // Stop in the middle of a chunk
+        let chunker = Chunker::new(ZPAQ::new(3)).max_size(5);
+        let mut incremental = chunker.incremental();
+        result.extend(incremental.feed(&DATA[..17]));
+        let state = incremental.state().to_bytes();
+
+        // Resume with a new Chunker
+        let state = ChunkerState::from_bytes(&state).unwrap();
+        assert_eq!(state.position(), 17);
+        let chunker = Chunker::new(ZPAQ::new(3)).max_size(5);
+        let mut incremental = chunker.resume_incremental(&state).unwrap();
+        result.extend(incremental.feed(&DATA[17..]));
+        result.extend(incremental.finish());
+
+        assert_eq!(result, vec![3, 8, 12, 14, 19, 24, 27, 32, 33]);
+    }
+
+    #[test]
+    fn test_resume_chunks() {
+        let mut result = Vec::new();
+
+        let chunker = Chunker::new(ZPAQ::new(3));
+        let mut chunks = chunker.chunks(DATA);
+        for _ in 0..3 {
+            let chunk_info = chunks.next().unwrap().unwrap();
+            result.push((chunk_info.start(), chunk_info.length()));
+        }
+        let state = chunks.state();
+        assert_eq!(state.position(), 12);
+        assert_eq!(state.chunk_index(), 3);
+
+        let chunker = Chunker::new(ZPAQ::new(3));
+        let chunks = chunker.resume_chunks(&DATA[12..], &state).unwrap();
+        for chunk_info in chunks {
+            let chunk_info = chunk_info.unwrap();
+            result.push((chunk_info.start(), chunk_info.length()));
+        }
+        assert_eq!(
+            result,
+            vec![
+                (0, 3), (3, 5), (8, 4), (12, 2),
+                (14, 6), (20, 6), (26, 7),
+            ]
+        );
+
+        // Errors after resuming count chunks from the start of the stream
+        let chunker = Chunker::new(ZPAQ::new(3));
+        let reader = Flaky::new(&DATA[12..], io::ErrorKind::Other);
+        let mut chunks = chunker.resume_chunks(reader, &state).unwrap();
+        let e = chunks.next().unwrap().err().unwrap();
+        assert_eq!((e.offset(), e.chunk_index(), e.partial_len()), (12, 3, 0));
+        let chunk_info = chunks.next().unwrap().unwrap();
+        assert_eq!((chunk_info.start(), chunk_info.length()), (12, 2));
+        let e = chunks.next().unwrap().err().unwrap();
+        assert_eq!((e.offset(), e.chunk_index(), e.partial_len()), (15, 4, 1));
+    }
+
+    #[test]
+    fn test_resume_past_4gib() {
+        // Go through 5 GiB, which doesn't fit in a usize on 32-bit targets
+        let data = vec![0u8; 1 << 26];
+        let chunker = Chunker::new(Never).max_size(3 << 30);
+        let mut incremental = chunker.incremental();
+        let mut result = Vec::new();
+        for _ in 0..80 {
+            result.extend(incremental.feed(&data));
+        }
+        let state = incremental.state().to_bytes();
+        let state = ChunkerState::from_bytes(&state).unwrap();
+        assert_eq!(state.position(), 5 << 30);
+
+        // The chunk that started at 3 GiB goes on
+        let chunker = Chunker::new(Never).max_size(3 << 30);
+        let mut incremental = chunker.resume_incremental(&state).unwrap();
+        for _ in 0..20 {
+            result.extend(incremental.feed(&data));
+        }
+        result.extend(incremental.finish());
+        assert_eq!(result, vec![3 << 30, 6 << 30, 25 << 28]);
+    }
+
+    #[test]
+    fn test_invalid_state() {
+        assert!(ChunkerState::from_bytes(b"").is_err());
+        let state = ChunkerState::from_bytes(&[2; 30]).unwrap();
+        let chunker = Chunker::new(ZPAQ::new(3));
+        assert!(chunker.resume_incremental(&state).is_err());
+    }
+
+    #[test]
+    fn test_resume_builder() {
+        for spec in &["zpaq:avg=16,max=64", "gear:min=4,avg=16,max=64"] {
+            let data: Vec<u8> =
+                (0..1000u32).map(|i| (i * i / 7) as u8).collect();
+            let builder = spec.parse::<ChunkerBuilder>().unwrap();
+            let expected: Vec<u64> = {
+                let mut incremental = builder.build().unwrap().incremental();
+                let mut expected: Vec<u64> = incremental.feed(&data).collect();
+                expected.extend(incremental.finish());
+                expected
+            };
+
+            let mut incremental = builder.build().unwrap().incremental();
+            let mut result: Vec<u64> = incremental.feed(&data[..500]).collect();
+            let state = incremental.state();
+            let mut incremental =
+                builder.build().unwrap().resume_incremental(&state).unwrap();
+            result.extend(incremental.feed(&data[500..]));
+            result.extend(incremental.finish());
+            assert_eq!(result, expected);
+        }
+    }
+
+    #[test]
+    fn test_parameters_mismatch() {
+        let mut incremental = Chunker::new(ZPAQ::new(3)).incremental();
+        incremental.feed(DATA);
+        let state = incremental.state();
+
+        let resume = |chunker: Chunker<_>| {
+            chunker.resume_incremental(&state).err().unwrap().kind()
+        };
+        let chunker = Chunker::new(ZPAQ::new(4));
+        assert_eq!(resume(chunker), io::ErrorKind::InvalidInput);
+        let chunker = Chunker::new(ZPAQ::new(3).with_seed(1));
+        assert_eq!(resume(chunker), io::ErrorKind::InvalidInput);
+
+        // Different limits, or a different algorithm
+        let mut incremental =
+            Chunker::new(ZPAQ::new(3)).max_size(64).incremental();
+        incremental.feed(DATA);
+        let state = incremental.state();
+        let chunker = Chunker::new(ZPAQ::new(3)).max_size(32);
+        assert!(chunker.resume_incremental(&state).is_err());
+        let state = "zpaq:avg=8,max=64"
+            .parse::<ChunkerBuilder>()
+            .unwrap()
+            .build()
+            .unwrap()
+            .incremental()
+            .state();
+        let chunker = "gear:avg=8,max=64"
+            .parse::<ChunkerBuilder>()
+            .unwrap()
+            .build()
+            .unwrap();
+        assert!(chunker.resume_incremental(&state).is_err());
+    }
+}
@@ -0,0 +1,314 @@
+//! Statistics on the sizes of chunks, to help tuning the parameters.
+
+use std::fmt;
+
+use super::{BoundaryKind, ChunkInfo};
+
+/// Accumulates statistics on the sizes of chunks.
+///
+/// Feed it the `ChunkInfo`s returned by `Chunker::chunks()`, then look at the
+/// distribution of sizes. This is useful to pick the parameters of a
+/// `ChunkerImpl`, or the `max_size()`, for your data.
+///
+/// ```
+/// # use cdchunking::{ChunkStats, Chunker, ZPAQ};
+/// # let reader: &[u8] = b"abcdefghijklmnopqrstuvwxyz1234567890";
+/// let chunker = Chunker::new(ZPAQ::new(3)).max_size(8);
+/// let mut stats = ChunkStats::new();
+/// for chunk_info in chunker.chunks(reader) {
+///     stats.add(&chunk_info.expect("Error reading from file"));
+/// }
+/// println!("{}", stats);
+/// ```
+///
+/// The sizes are not kept, so this uses a small, fixed amount of memory
+/// however many chunks you add.
+#[derive(Clone, Debug, Default)]
+pub struct ChunkStats {
+    count: u64,
+    total: u64,
+    min: u64,
+    max: u64,
+    running_mean: f64, // With m2, for the variance (Welford's algorithm)
+    m2: f64,
+    natural: u64,
+    forced: u64,
+    histogram: Vec<u64>,
+    fine_histogram: Vec<u64>, // For the median, see fine_bucket()
+}
+
+impl ChunkStats {
+    /// Creates an empty accumulator.
+    pub fn new() -> ChunkStats {
+        Default::default()
+    }
+
+    /// Adds a chunk.
+    pub fn add(&mut self, chunk: &ChunkInfo) {
+        let length = chunk.length();
+        if self.count == 0 || length < self.min {
+            self.min = length;
+        }
+        if self.count == 0 || length > self.max {
+            self.max = length;
+        }
+        self.count += 1;
+        self.total += length;
+        let delta = length as f64 - self.running_mean;
+        self.running_mean += delta / self.count as f64;
+        self.m2 += delta * (length as f64 - self.running_mean);
+        match chunk.kind() {
+            BoundaryKind::Natural => self.natural += 1,
+            BoundaryKind::MaxSize => self.forced += 1,
+            BoundaryKind::EndOfStream => {}
+        }
+        let bucket = bucket(length);
+        if self.histogram.len() <= bucket {
+            self.histogram.resize(bucket + 1, 0);
+        }
+        self.histogram[bucket] += 1;
+        let bucket = fine_bucket(length);
+        if self.fine_histogram.len() <= bucket {
+            self.fine_histogram.resize(bucket + 1, 0);
+        }
+        self.fine_histogram[bucket] += 1;
+    }
+
+    /// The number of chunks.
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// The total size of all the chunks.
+    pub fn total_size(&self) -> u64 {
+        self.total
+    }
+
+    /// The size of the smallest chunk.
+    pub fn min(&self) -> Option<u64> {
+        if self.count == 0 {
+            None
+        } else {
+            Some(self.min)
+        }
+    }
+
+    /// The size of the largest chunk.
+    pub fn max(&self) -> Option<u64> {
+        if self.count == 0 {
+            None
+        } else {
+            Some(self.max)
+        }
+    }
+
+    /// The mean chunk size, or 0 if there are no chunks.
+    pub fn mean(&self) -> f64 {
+        if self.count == 0 {
+            0.0
+        } else {
+            self.total as f64 / self.count as f64
+        }
+    }
+
+    /// The median chunk size.
+    ///
+    /// If there is an even number of chunks, this is the smallest of the two
+    /// middle sizes. Since the sizes are not kept, this is only exact for
+    /// sizes under 64 bytes; larger sizes are rounded down, by less than 1/32.
+    pub fn median(&self) -> Option<u64> {
+        if self.count == 0 {
+            return None;
+        }
+        let mut rank = (self.count - 1) / 2;
+        for (i, &count) in self.fine_histogram.iter().enumerate() {
+            if rank < count {
+                let length = fine_bucket_start(i);
+                return Some(length.max(self.min).min(self.max));
+            }
+            rank -= count;
+        }
+        unreachable!()
+    }
+
+    /// The standard deviation of chunk sizes, or 0 if there are no chunks.
+    pub fn std_dev(&self) -> f64 {
+        if self.count == 0 {
+            return 0.0;
+        }
+        (self.m2 / self.count as f64).sqrt()
+    }
+
+    /// Histogram of the chunk sizes, with buckets on a log2 scale.
+    ///
+    /// Bucket `i` counts the chunks with a size between `2^i` (included) and
+    /// `2^(i+1)` (excluded). Empty chunks are counted in bucket 0.
+    pub fn histogram(&self) -> &[u64] {
+        &self.histogram
+    }
+
+    /// The number of chunks that were cut because they reached the maximum
+    /// size.
+    pub fn forced(&self) -> u64 {
+        self.forced
+    }
+
+    /// The number of chunks that were cut at a boundary found in the content.
+    pub fn natural(&self) -> u64 {
+        self.natural
+    }
+
+    /// The number of chunks that ended with the input, normally one per
+    /// stream.
+    pub fn end_of_stream(&self) -> u64 {
+        self.count() - self.natural - self.forced
+    }
+
+    /// The fraction of chunks that were cut because they reached the maximum
+    /// size, or 0 if there are no chunks.
+    pub fn forced_fraction(&self) -> f64 {
+        if self.count == 0 {
+            0.0
+        } else {
+            self.forced as f64 / self.count as f64
+        }
+    }
+}
+
+fn bucket(length: u64) -> usize {
+    if length == 0 {
+        0
+    } else {
+        (63 - length.leading_zeros()) as usize
+    }
+}
+
+// Each power of two is split in 2^FINE_BITS buckets
+const FINE_BITS: u32 = 5;
+
+/// Bucket of the finer histogram: sizes under 2^(FINE_BITS+1) get their own
+/// bucket, larger ones share it with sizes within 1/2^FINE_BITS.
+fn fine_bucket(length: u64) -> usize {
+    if length < 2 << FINE_BITS {
+        return length as usize;
+    }
+    let shift = 63 - length.leading_zeros() - FINE_BITS;
+    ((shift as usize) << FINE_BITS) + (length >> shift) as usize
+}
+
+/// The smallest size in a bucket of the finer histogram.
+fn fine_bucket_start(bucket: usize) -> u64 {
+    if bucket < 2 << FINE_BITS {
+        return bucket as u64;
+    }
+    let shift = (bucket >> FINE_BITS) - 1;
+    ((bucket - (shift << FINE_BITS)) as u64) << shift
+}
+
+impl Extend<ChunkInfo> for ChunkStats {
+    fn extend<T: IntoIterator<Item = ChunkInfo>>(&mut self, iter: T) {
+        for chunk in iter {
+            self.add(&chunk);
+        }
+    }
+}
+
+impl fmt::Display for ChunkStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{} chunks, {} bytes", self.count(), self.total_size())?;
+        if let (Some(min), Some(max), Some(median)) =
+            (self.min(), self.max(), self.median())
+        {
+            writeln!(
+                f,
+                "size: min {}, max {}, mean {:.0}, median {}, std dev {:.0}",
+                min,
+                max,
+                self.mean(),
+                median,
+                self.std_dev()
+            )?;
+        }
+        writeln!(
+            f,
+            "natural: {}, cut by max_size: {} ({:.1}%), end of stream: {}",
+            self.natural(),
+            self.forced(),
+            self.forced_fraction() * 100.0,
+            self.end_of_stream()
+        )?;
+        for (i, &count) in self.histogram.iter().enumerate() {
+            if count > 0 {
+                writeln!(
+                    f,
+                    "  {:>10} - {:>10}: {}",
+                    1u64 << i,
+                    (2u64 << i) - 1,
+                    count
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{BoundaryKind, ChunkInfo, Chunker, ZPAQ};
+    use super::{fine_bucket, fine_bucket_start, ChunkStats};
+
+    #[test]
+    fn test_stats() {
+        let data = b"defghijklmnopqrstuvwxyz1234567890";
+        let mut stats = ChunkStats::new();
+        stats.extend(
+            Chunker::new(ZPAQ::new(3))
+                .max_size(5)
+                .chunks(&data[..])
+                .map(|c| c.unwrap()),
+        );
+        // Chunks: 3, 5, 4, 2, 5, 5, 3, 5, 1
+        assert_eq!(stats.count(), 9);
+        assert_eq!(stats.total_size(), 33);
+        assert_eq!(stats.min(), Some(1));
+        assert_eq!(stats.max(), Some(5));
+        assert_eq!(stats.median(), Some(4));
+        assert!((stats.mean() - 33.0 / 9.0).abs() < 1e-9);
+        assert!((stats.std_dev() - ::std::f64::consts::SQRT_2).abs() < 1e-9);
+        assert_eq!(stats.histogram(), &[1, 3, 5]);
+        assert_eq!(stats.forced(), 2);
+        assert_eq!(stats.natural(), 6);
+        assert_eq!(stats.end_of_stream(), 1);
+
+        let empty = ChunkStats::new();
+        assert_eq!(empty.min(), None);
+        assert_eq!(empty.median(), None);
+        assert_eq!(empty.mean(), 0.0);
+        assert!(empty.histogram().is_empty());
+    }
+
+    #[test]
+    fn test_median() {
+        let mut stats = ChunkStats::new();
+        let lengths = [100_000u64, 8000, 8191, 8192, 9000, 1 << 40, 63];
+        for &length in &lengths {
+            stats.add(&ChunkInfo {
+                start: 0,
+                length,
+                kind: BoundaryKind::Natural,
+            });
+        }
+        // The exact median is 8192, and a bucket starts there
+        assert_eq!(stats.median(), Some(8192));
+        assert_eq!(stats.min(), Some(63));
+        assert_eq!(stats.max(), Some(1 << 40));
+
+        // Each bucket starts within 1/32 of the sizes in it
+        for &length in &[64u64, 100, 8191, 8192, 9000, 1 << 40, !0] {
+            let start = fine_bucket_start(fine_bucket(length));
+            assert!(start <= length);
+            assert!(length - start <= length / 32);
+            assert_eq!(fine_bucket(start), fine_bucket(length));
+        }
+    }
+}
@@ -0,0 +1,31 @@
+//! Fixtures shared by the tests of the different modules.
+
+use super::xorshift::XorShift;
+use super::{Chunker, ChunkerImpl, Gear, ZPAQ};
+
+/// Pseudo-random data, the same for the same seed.
+pub fn random_data(size: usize, seed: u64) -> Vec<u8> {
+    XorShift::new(seed).bytes(size)
+}
+
+/// A test that is run against a chunker.
+///
+/// It gets a factory rather than a chunker, since most of the ways to chunk
+/// consume the `Chunker`.
+pub trait Check {
+    fn check<I, F>(&mut self, chunker: F)
+    where
+        I: ChunkerImpl + Clone + Send + Sync,
+        F: Fn() -> Chunker<I>;
+}
+
+/// Runs a test against each algorithm, with and without a maximum size.
+///
+/// The chunks average `1 << bits` bytes.
+pub fn check_chunkers<C: Check>(test: &mut C, bits: usize) {
+    let max_size = 4 << bits;
+    test.check(|| Chunker::new(ZPAQ::new(bits)));
+    test.check(|| Chunker::new(ZPAQ::new(bits)).max_size(max_size));
+    test.check(|| Chunker::new(Gear::new(bits)));
+    test.check(|| Chunker::new(Gear::new(bits)).max_size(max_size));
+}
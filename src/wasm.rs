@@ -0,0 +1,192 @@
+//! JavaScript bindings, to chunk data in the browser with wasm-bindgen.
+//!
+//! The chunker is built from a spec string, the same as `ChunkerBuilder`'s,
+//! so the boundaries are the same as those found by a Rust program using that
+//! spec.
+
+use sha2::{Digest, Sha256};
+use wasm_bindgen::prelude::*;
+
+use super::{AnyChunker, Chunker, ChunkerBuilder, Incremental, SizeLimited};
+
+/// Chunker for JavaScript, finding boundaries in data pushed piece by piece.
+///
+/// It is exported as `Chunker`:
+///
+/// ```js
+/// const chunker = new Chunker("gear:min=2k,avg=8k,max=64k", true);
+/// for (const piece of pieces) {
+///     const chunks = chunker.push(piece);
+///     console.log(chunks.ends, chunks.digests);
+/// }
+/// const last = chunker.finish();
+/// ```
+#[wasm_bindgen(js_name = Chunker)]
+pub struct WasmChunker {
+    incremental: Incremental<SizeLimited<AnyChunker>>,
+    hasher: Option<Sha256>,
+}
+
+#[wasm_bindgen(js_class = Chunker)]
+impl WasmChunker {
+    /// Creates a chunker from a spec, like `"zpaq:avg=8k,max=64k"`.
+    ///
+    /// If `digests` is true, the SHA-256 digest of each chunk is computed.
+    /// Throws if the spec is invalid.
+    #[wasm_bindgen(constructor)]
+    pub fn new(spec: &str, digests: bool) -> Result<WasmChunker, JsError> {
+        let chunker = spec
+            .parse::<ChunkerBuilder>()
+            .and_then(|builder| builder.build())
+            .map_err(|e| JsError::new(&e.to_string()))?;
+        Ok(WasmChunker::from_chunker(chunker, digests))
+    }
+
+    fn from_chunker(
+        chunker: Chunker<SizeLimited<AnyChunker>>,
+        digests: bool,
+    ) -> WasmChunker {
+        WasmChunker {
+            incremental: chunker.incremental(),
+            hasher: if digests { Some(Sha256::new()) } else { None },
+        }
+    }
+
+    /// Feeds more data, returning the chunks that end in it.
+    pub fn push(&mut self, data: &[u8]) -> WasmChunks {
+        let start = self.incremental.position();
+        let mut chunks = WasmChunks {
+            ends: Vec::new(),
+            digests: Vec::new(),
+        };
+        let mut hashed = 0;
+        for end in self.incremental.feed(data) {
+            if let Some(ref mut hasher) = self.hasher {
+                let split = (end - start) as usize;
+                hasher.update(&data[hashed..split]);
+                chunks.digests.extend_from_slice(&hasher.finalize_reset());
+                hashed = split;
+            }
+            chunks.ends.push(end as f64);
+        }
+        if let Some(ref mut hasher) = self.hasher {
+            hasher.update(&data[hashed..]);
+        }
+        chunks
+    }
+
+    /// Ends the stream, returning the last chunk if there is data left.
+    ///
+    /// If no data was pushed at all, this returns one empty chunk, like
+    /// `Incremental::finish()`.
+    ///
+    /// The chunker can't be used after this.
+    pub fn finish(self) -> WasmChunks {
+        let mut chunks = WasmChunks {
+            ends: Vec::new(),
+            digests: Vec::new(),
+        };
+        if let Some(end) = self.incremental.finish() {
+            if let Some(hasher) = self.hasher {
+                chunks.digests.extend_from_slice(&hasher.finalize());
+            }
+            chunks.ends.push(end as f64);
+        }
+        chunks
+    }
+
+    /// How much data has been pushed so far.
+    pub fn position(&self) -> f64 {
+        self.incremental.position() as f64
+    }
+}
+
+/// Chunks found by `WasmChunker`, exported as `Chunks`.
+#[wasm_bindgen(js_name = Chunks)]
+pub struct WasmChunks {
+    ends: Vec<f64>,
+    digests: Vec<u8>,
+}
+
+#[wasm_bindgen(js_class = Chunks)]
+impl WasmChunks {
+    /// The offsets where the chunks end, from the start of the stream.
+    ///
+    /// These are exact for streams smaller than 2^53 bytes.
+    #[wasm_bindgen(getter)]
+    pub fn ends(&self) -> Vec<f64> {
+        self.ends.clone()
+    }
+
+    /// The SHA-256 digests of the chunks, 32 bytes each, or nothing if they
+    /// were not requested.
+    #[wasm_bindgen(getter)]
+    pub fn digests(&self) -> Vec<u8> {
+        self.digests.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use sha2::{Digest, Sha256};
+    #[cfg(target_arch = "wasm32")]
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    use super::super::testing::random_data;
+    use super::super::ChunkerBuilder;
+    use super::WasmChunker;
+
+    const SPEC: &str = "gear:min=64,avg=256,max=1k";
+
+    #[cfg_attr(not(target_arch = "wasm32"), test)]
+    #[cfg_attr(target_arch = "wasm32", wasm_bindgen_test)]
+    fn test_push() {
+        let data = random_data(10_000, 0);
+        let builder = SPEC.parse::<ChunkerBuilder>().unwrap();
+        let mut expected_ends = Vec::new();
+        let mut expected_digests = Vec::new();
+        let mut end = 0;
+        for slice in builder.build().unwrap().slices(&data) {
+            end += slice.len();
+            expected_ends.push(end as f64);
+            expected_digests.extend_from_slice(&Sha256::digest(slice));
+        }
+
+        for &digests in &[false, true] {
+            let mut chunker = WasmChunker::new(SPEC, digests).unwrap();
+            let mut ends = Vec::new();
+            let mut all_digests = Vec::new();
+            for piece in data.chunks(300) {
+                let chunks = chunker.push(piece);
+                ends.extend(chunks.ends());
+                all_digests.extend(chunks.digests());
+            }
+            assert_eq!(chunker.position(), data.len() as f64);
+            let chunks = chunker.finish();
+            ends.extend(chunks.ends());
+            all_digests.extend(chunks.digests());
+
+            assert_eq!(ends, expected_ends);
+            if digests {
+                assert_eq!(all_digests, expected_digests);
+            } else {
+                assert!(all_digests.is_empty());
+            }
+        }
+    }
+
+    #[cfg_attr(not(target_arch = "wasm32"), test)]
+    #[cfg_attr(target_arch = "wasm32", wasm_bindgen_test)]
+    fn test_empty() {
+        let chunker = WasmChunker::new(SPEC, true).unwrap();
+        let chunks = chunker.finish();
+        assert_eq!(chunks.ends(), vec![0.0]);
+        assert_eq!(chunks.digests(), Sha256::digest(b"").to_vec());
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    #[wasm_bindgen_test]
+    fn test_invalid_spec() {
+        assert!(WasmChunker::new("zpaq:avg=8k,max=4k", false).is_err());
+    }
+}
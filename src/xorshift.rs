@@ -0,0 +1,30 @@
+//! Small xorshift generator, so that random data and edits are reproducible
+//! from a seed.
+
+pub struct XorShift(u64);
+
+impl XorShift {
+    pub fn new(seed: u64) -> XorShift {
+        // Mix small seeds, and avoid the zero state, which stays zero forever
+        let state = seed ^ 0x2545_f491_4f6c_dd1d;
+        XorShift(if state == 0 { 1 } else { state })
+    }
+
+    pub fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    pub fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+
+    /// Pseudo-random bytes, from the high bits of the state.
+    // Only used by the tests and the benchmarks
+    #[allow(dead_code)]
+    pub fn bytes(&mut self, size: usize) -> Vec<u8> {
+        (0..size).map(|_| (self.next() >> 32) as u8).collect()
+    }
+}